@@ -0,0 +1,245 @@
+//! HID interface with boot protocol support, for firmware setup screens and KVMs that
+//! only read the fixed boot keyboard and mouse reports. It follows the embassy HID class,
+//! which always declares no boot subclass and rejects GET/SET_PROTOCOL.
+
+use embassy_rp::{
+    peripherals::USB,
+    usb::Driver,
+};
+use embassy_usb::{
+    Builder,
+    Handler,
+    class::hid::{Config as HidConfig, RequestHandler, ReportId},
+    control::{InResponse, OutResponse, Recipient, Request, RequestType},
+    driver::{self, Endpoint, EndpointError, EndpointIn, EndpointOut},
+};
+use portable_atomic::{AtomicU8, Ordering};
+use static_cell::StaticCell;
+
+type EpIn = <Driver<'static, USB> as driver::Driver<'static>>::EndpointIn;
+type EpOut = <Driver<'static, USB> as driver::Driver<'static>>::EndpointOut;
+
+const USB_CLASS_HID: u8         = 0x03;
+const SUBCLASS_BOOT: u8         = 0x01;
+const DESC_TYPE_HID: u8         = 0x21;
+const DESC_TYPE_REPORT: u8      = 0x22;
+
+const REQ_GET_REPORT: u8        = 0x01;
+const REQ_GET_IDLE: u8          = 0x02;
+const REQ_GET_PROTOCOL: u8      = 0x03;
+const REQ_SET_REPORT: u8        = 0x09;
+const REQ_SET_IDLE: u8          = 0x0A;
+const REQ_SET_PROTOCOL: u8      = 0x0B;
+
+/// GET/SET_PROTOCOL values
+const BOOT_PROTOCOL: u8         = 0;
+const REPORT_PROTOCOL: u8       = 1;
+
+/// Buttons, X and Y, all a boot mouse report carries
+const BOOT_MOUSE_REPORT_SIZE: usize = 3;
+/// Modifiers, a reserved byte and six keycodes
+pub const BOOT_KEYBOARD_REPORT_SIZE: usize = 8;
+/// Largest output report read, the keyboard LEDs take one byte
+const OUT_REPORT_SIZE: usize    = 8;
+
+/// Device the firmware expects behind a boot interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootDevice {
+    Keyboard = 1,
+    Mouse = 2,
+}
+
+/// Storage an interface shares between its control requests and endpoints
+pub struct State {
+    protocol: AtomicU8,
+    control: StaticCell<Control>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        State { protocol: AtomicU8::new(REPORT_PROTOCOL), control: StaticCell::new() }
+    }
+}
+
+/// Class requests of the interface, forwarded to the request handler like the embassy HID class does
+struct Control {
+    interface: u16,
+    report_descriptor: &'static [u8],
+    hid_descriptor: [u8; 9],
+    request_handler: Option<&'static mut dyn RequestHandler>,
+    protocol: &'static AtomicU8,
+}
+
+/// Report type and ID of GET/SET_REPORT
+fn report_id(value: u16) -> Option<ReportId> {
+    let id = value as u8;
+    match value >> 8 {
+        1 => Some(ReportId::In(id)),
+        2 => Some(ReportId::Out(id)),
+        3 => Some(ReportId::Feature(id)),
+        _ => None,
+    }
+}
+
+impl Handler for Control {
+    fn reset(&mut self) {
+        // Hosts expect the report protocol after a reset
+        self.protocol.store(REPORT_PROTOCOL, Ordering::Relaxed);
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if (req.request_type, req.recipient, req.index) != (RequestType::Class, Recipient::Interface, self.interface) {
+            return None;
+        }
+        let response = match req.request {
+            REQ_SET_IDLE => OutResponse::Accepted,
+            REQ_SET_PROTOCOL if req.value as u8 <= REPORT_PROTOCOL => {
+                log::info!("Interface {} switched to the {} protocol", self.interface,
+                    if req.value as u8 == BOOT_PROTOCOL { "boot" } else { "report" });
+                self.protocol.store(req.value as u8, Ordering::Relaxed);
+                OutResponse::Accepted
+            }
+            REQ_SET_REPORT => match (report_id(req.value), self.request_handler.as_mut()) {
+                (Some(id), Some(handler)) => handler.set_report(id, data),
+                _ => OutResponse::Rejected,
+            },
+            _ => OutResponse::Rejected,
+        };
+        Some(response)
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if req.index != self.interface {
+            return None;
+        }
+        let response = match (req.request_type, req.recipient) {
+            (RequestType::Standard, Recipient::Interface) if req.request == Request::GET_DESCRIPTOR => {
+                match (req.value >> 8) as u8 {
+                    DESC_TYPE_REPORT => InResponse::Accepted(self.report_descriptor),
+                    DESC_TYPE_HID => InResponse::Accepted(&self.hid_descriptor),
+                    _ => InResponse::Rejected,
+                }
+            }
+            (RequestType::Class, Recipient::Interface) => match req.request {
+                REQ_GET_REPORT => {
+                    let len = match (report_id(req.value), self.request_handler.as_mut()) {
+                        (Some(id), Some(handler)) => handler.get_report(id, buf),
+                        _ => None,
+                    };
+                    match len {
+                        Some(len) => InResponse::Accepted(&buf[..len]),
+                        None => InResponse::Rejected,
+                    }
+                }
+                // Reports are only sent on change, never repeated
+                REQ_GET_IDLE => {
+                    buf[0] = 0;
+                    InResponse::Accepted(&buf[..1])
+                }
+                REQ_GET_PROTOCOL => {
+                    buf[0] = self.protocol.load(Ordering::Relaxed);
+                    InResponse::Accepted(&buf[..1])
+                }
+                _ => InResponse::Rejected,
+            },
+            _ => return None,
+        };
+        Some(response)
+    }
+}
+
+/// Input reports of a boot capable interface
+pub struct BootHid {
+    ep_in: EpIn,
+    device: BootDevice,
+    protocol: &'static AtomicU8,
+}
+
+/// Output reports of a boot keyboard, the LEDs
+pub struct BootHidReader {
+    ep_out: EpOut,
+}
+
+impl BootHid {
+    /// Adds the interface, a keyboard also gets an OUT endpoint for its LEDs
+    pub fn new(
+        builder: &mut Builder<'static, Driver<'static, USB>>,
+        state: &'static State,
+        device: BootDevice,
+        config: HidConfig<'static>
+    ) -> (Self, Option<BootHidReader>) {
+        let len = config.report_descriptor.len() as u16;
+        let hid_descriptor = [
+            9,                      // Length
+            DESC_TYPE_HID,
+            0x11, 0x01,             // HID 1.11
+            0x00,                   // Not localized
+            0x01,                   // One class descriptor
+            DESC_TYPE_REPORT,
+            len as u8, (len >> 8) as u8,
+        ];
+
+        let mut func = builder.function(USB_CLASS_HID, SUBCLASS_BOOT, device as u8);
+        let mut iface = func.interface();
+        let interface = u8::from(iface.interface_number()) as u16;
+        let mut alt = iface.alt_setting(USB_CLASS_HID, SUBCLASS_BOOT, device as u8, None);
+        alt.descriptor(DESC_TYPE_HID, &hid_descriptor[2..]);
+        let ep_in = alt.endpoint_interrupt_in(None, config.max_packet_size, config.poll_ms);
+        let reader = (device == BootDevice::Keyboard)
+            .then(|| BootHidReader { ep_out: alt.endpoint_interrupt_out(None, config.max_packet_size, config.poll_ms) });
+        drop(func);
+
+        builder.handler(state.control.init(Control {
+            interface,
+            report_descriptor: config.report_descriptor,
+            hid_descriptor,
+            request_handler: config.request_handler,
+            protocol: &state.protocol,
+        }));
+
+        (BootHid { ep_in, device, protocol: &state.protocol }, reader)
+    }
+
+    /// Whether the host asked for the boot reports
+    pub fn boot_protocol(&self) -> bool {
+        self.protocol.load(Ordering::Relaxed) == BOOT_PROTOCOL
+    }
+
+    /// Sends a report protocol report, cut down to the boot report while the host asks for it
+    pub async fn write(&mut self, report: &[u8]) -> Result<(), EndpointError> {
+        let boot_size = match self.device {
+            BootDevice::Keyboard => BOOT_KEYBOARD_REPORT_SIZE,
+            BootDevice::Mouse => BOOT_MOUSE_REPORT_SIZE,
+        };
+        let report = match self.boot_protocol() {
+            true => &report[..report.len().min(boot_size)],
+            false => report,
+        };
+        self.ep_in.write(report).await
+    }
+}
+
+impl BootHidReader {
+    /// Hands every output report to `handler`, as the embassy HID reader does
+    pub async fn run<T: RequestHandler>(mut self, handler: &mut T) -> ! {
+        let mut buf = [0; OUT_REPORT_SIZE];
+        loop {
+            self.ep_out.wait_enabled().await;
+            loop {
+                match self.ep_out.read(&mut buf).await {
+                    Ok(len) => {
+                        handler.set_report(ReportId::Out(0), &buf[..len]);
+                    }
+                    Err(EndpointError::Disabled) => break,
+                    Err(e) => log::warn!("Failed to read output report: {:?}", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use core::fmt::Write;
+
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_time::Instant;
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use heapless::String;
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::{
+    definitions::PAIRING_WINDOW,
+    cli::{self, Command, Response, After, Target, HELP, shown_value},
+    config::{SharedSettings, ConfigKey, ConfigValue, ConfigError, Hand},
+    protocol::ProfileAction,
+    storage::SharedStore,
+};
+
+use crate::{status::SharedStatus, relay::GloveRelay, pairing};
+
+pub type CliDevice = CdcAcmClass<'static, Driver<'static, USB>>;
+
+
+/// Persists a dongle setting, then applies it
+async fn set_setting(key: ConfigKey, text: &str, settings: &'static SharedSettings, store: &'static SharedStore, out: &mut Response) {
+    let mut store = store.lock().await;
+    let mut updated = settings.lock(|settings| settings.borrow().clone());
+    let changed: Result<(), ConfigError> = updated.get(key).parse_as(text).and_then(|value| updated.set(key, value));
+    if let Err(e) = changed {
+        let _ = write!(out, "error: {}\r\n", e);
+        return;
+    }
+    if let Err(e) = store.save(&updated, key) {
+        let _ = write!(out, "error: {:?} while saving\r\n", e);
+        return;
+    }
+    let value = updated.get(key);
+    settings.lock(|settings| *settings.borrow_mut() = updated);
+    // Dongle settings are read while booting, except the pointer hand, the MIDI mapping and the paired gloves
+    let _ = match key {
+        ConfigKey::PointerHand | ConfigKey::MidiChannel | ConfigKey::MidiCc | ConfigKey::MidiNote
+        | ConfigKey::PairedGlove1 | ConfigKey::PairedGlove2 => write!(out, "{}\r\n", value),
+        _ => write!(out, "saved, applied on next boot\r\n"),
+    };
+}
+
+/// Changes a glove setting through the link, the glove persists it
+async fn set_glove_setting(hand: Option<Hand>, key: ConfigKey, text: &str, settings: &'static SharedSettings, relay: &'static GloveRelay, out: &mut Response) {
+    // Both boards share the settings layout, the local value gives the type
+    let value = match settings.lock(|settings| settings.borrow().get(key)).parse_as(text) {
+        Ok(value) => value,
+        Err(e) => {
+            let _ = write!(out, "error: {}\r\n", e);
+            return;
+        }
+    };
+    match relay.set(hand, key, value).await {
+        Ok(value) => shown_value(key, &value, out),
+        Err(e) => { let _ = write!(out, "error: {}\r\n", e); }
+    }
+}
+
+fn write_status(status: &'static SharedStatus, out: &mut Response) {
+    let gloves = status.lock(|status| *status.borrow());
+    let _ = write!(out, "uptime: {} s\r\n", Instant::now().as_secs());
+    for (slot, glove) in gloves.iter().enumerate() {
+        let Some(peer) = glove.peer else {
+            let _ = write!(out, "glove {}: disconnected\r\n", slot);
+            continue;
+        };
+        let _ = write!(out, "glove {}: connected from {}", slot, peer);
+        if let (Some(device_id), Some(hand)) = (glove.device_id, glove.hand) {
+            let _ = write!(out, ", {:08x}, {} hand", device_id, hand);
+        }
+        let _ = write!(out, "\r\n  power: {:?}\r\n", glove.power);
+        // MIDI is played from the gamepad stream only
+        let _ = match glove.gamepad {
+            Some(_) if cfg!(feature = "midi") => write!(out, "  mode: gamepad, playing MIDI\r\n"),
+            Some(_) => write!(out, "  mode: gamepad\r\n"),
+            None => write!(out, "  mode: pointer\r\n"),
+        };
+        let _ = match glove.battery {
+            Some(battery) => write!(out, "  battery: {} mV, {}%\r\n", battery.millivolts, battery.percent),
+            None => write!(out, "  battery: unknown\r\n"),
+        };
+        let _ = out.write_str("  link:");
+        let _ = match glove.link.rtt {
+            Some(rtt) => write!(out, " rtt {} ms", rtt.as_millis()),
+            None => write!(out, " rtt unknown"),
+        };
+        if let Some(loss) = glove.link.loss_percent {
+            let _ = write!(out, ", loss {}%", loss);
+        }
+        if let Some(rssi) = glove.link.rssi {
+            let _ = write!(out, ", signal {} dBm", rssi);
+        }
+        let _ = out.write_str("\r\n");
+    }
+}
+
+async fn execute(
+    command: Command<'_>,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore,
+    status: &'static SharedStatus,
+    relay: &'static GloveRelay,
+    out: &mut Response
+) -> After {
+    match command {
+        Command::Help => { let _ = out.write_str(HELP); }
+        Command::Status => write_status(status, out),
+        Command::List(Target::Dongle) => {
+            let current = settings.lock(|settings| settings.borrow().clone());
+            for key in ConfigKey::ALL {
+                let _ = write!(out, "{} = ", key.name());
+                shown_value(key, &current.get(key), out);
+            }
+        }
+        Command::Get(Target::Dongle, key) => {
+            let value = settings.lock(|settings| settings.borrow().get(key));
+            shown_value(key, &value, out);
+        }
+        Command::Set(Target::Dongle, key, text) => {
+            set_setting(key, text, settings, store, out).await;
+            let gamepad = status.lock(|status| status.borrow().iter().any(|glove| glove.gamepad.is_some()));
+            if matches!(key, ConfigKey::MidiChannel | ConfigKey::MidiCc | ConfigKey::MidiNote) && !gamepad {
+                let _ = out.write_str("note: MIDI only plays gloves in gamepad mode, see set glove output_mode gamepad\r\n");
+            }
+        }
+        Command::List(Target::Glove(hand)) => {
+            for key in ConfigKey::ALL {
+                let _ = write!(out, "{} = ", key.name());
+                match relay.get(hand, key).await {
+                    Ok(value) => shown_value(key, &value, out),
+                    Err(e) => {
+                        let _ = write!(out, "error: {}\r\n", e);
+                        break;
+                    }
+                }
+            }
+        }
+        Command::Get(Target::Glove(hand), key) => match relay.get(hand, key).await {
+            Ok(value) => shown_value(key, &value, out),
+            Err(e) => { let _ = write!(out, "error: {}\r\n", e); }
+        },
+        Command::Set(Target::Glove(hand), key, text) => set_glove_setting(hand, key, text, settings, relay, out).await,
+        Command::Reboot(Target::Glove(hand)) => {
+            let _ = match relay.reboot(hand).await {
+                Ok(()) => write!(out, "glove rebooting\r\n"),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            };
+        }
+        Command::Calibrate(hand) => {
+            let _ = match relay.calibrate(hand).await {
+                Ok(bands) => write!(out, "{}\r\n", bands),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            };
+        }
+        Command::Profile(hand, action, name) => {
+            let Ok(name) = String::try_from(name) else {
+                let _ = write!(out, "error: {}\r\n", ConfigError::OutOfRange);
+                return After::Prompt;
+            };
+            let _ = match relay.profile(hand, action, name).await {
+                Ok(ConfigValue::Text(names)) if action == ProfileAction::List => write!(out, "{}\r\n", names),
+                Ok(active) => write!(out, "active profile: {}\r\n", active),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            };
+        }
+        Command::Buzz(hand, pattern) => {
+            if let Err(e) = relay.haptic(hand, pattern).await {
+                let _ = write!(out, "error: {}\r\n", e);
+            }
+        }
+        Command::Pair => {
+            pairing::open_window();
+            let _ = write!(out, "pairing for {} s, connect the new glove\r\n", PAIRING_WINDOW.as_secs());
+        }
+        Command::Reboot(Target::Dongle) => {
+            let _ = out.write_str("rebooting\r\n");
+            return After::Reboot;
+        }
+    }
+    After::Prompt
+}
+
+
+#[embassy_executor::task]
+pub async fn cli_task(
+    class: CliDevice,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore,
+    status: &'static SharedStatus,
+    relay: &'static GloveRelay
+) -> ! {
+    cli::run(class, async |command, out| execute(command, settings, store, status, relay, out).await).await
+}
@@ -0,0 +1,39 @@
+use core::net::Ipv4Addr;
+
+use embassy_net::{
+    Stack,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::Timer;
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::{
+    definitions::{TCP_ENDPOINT, DISCOVERY_PORT, BEACON_PERIOD},
+    protocol::Beacon,
+};
+
+
+/// Broadcasts the dongle beacon so gloves without a fixed dongle address can find it
+#[embassy_executor::task]
+pub async fn beacon_task(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 16];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 16];
+
+    stack.wait_config_up().await;
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    // Any local port, gloves listen on the discovery port
+    if let Err(e) = socket.bind(0) {
+        log::warn!("Beacon socket bind error: {:?}", e);
+    }
+    log::info!("Broadcasting beacon on UDP {}", DISCOVERY_PORT);
+
+    let beacon = Beacon { tcp_port: TCP_ENDPOINT }.to_be_bytes();
+    loop {
+        if let Err(e) = socket.send_to(&beacon, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)).await {
+            log::warn!("Beacon send error: {:?}", e);
+        }
+        Timer::after(BEACON_PERIOD).await;
+    }
+}
@@ -4,38 +4,94 @@ use embassy_rp::{
 };
 use embassy_usb::{
     class::{
-        hid::{HidReaderWriter, Config as HidConfig, State as HidState},
+        hid::{HidWriter, Config as HidConfig, State as HidState},
         cdc_acm::{CdcAcmClass, State as CdcState},
     },
-    UsbDevice
+    UsbDevice,
+    Handler,
 };
+use embassy_futures::select::{select3, Either3};
 use embassy_usb_logger::MAX_PACKET_SIZE;
 use embassy_sync::{
     channel::Receiver,
+    signal::Signal,
     blocking_mutex::raw::CriticalSectionRawMutex,
 };
-use usbd_hid::descriptor::{MouseReport, KeyboardReport, MediaKeyboardReport, MediaKey, SerializedDescriptor};
+use usbd_hid::descriptor::{MouseReport, MediaKeyboardReport};
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
 use shared::{
     definitions::{
-        CHANNEL_SIZE,
-        MOUSE_POLL_MS
+        HID_QUEUE_SIZE,
+        BATTERY_POLL_MS,
     },
-    custom_hid::HidInstruction
+    custom_hid::{HidInstruction, GamepadReport},
+    protocol::BatteryStatus,
+    cli::CLI_PACKET_SIZE,
+};
+
+use crate::{
+    cli::CliDevice,
+    merge::HeldInput,
+    hid_reports::{HidReports, KeyboardOutput, scroll_steps, reset_scroll_resolution, keyboard_leds},
+    relay::GloveRelay,
+    midi::{self, MidiOutput},
+    telemetry::{TelemetryDevice, TELEMETRY_PACKET_SIZE},
 };
 
 // USB Descriptors
-static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static CONFIG_DESCRIPTOR: StaticCell<[u8; 1024]> = StaticCell::new();
 static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
 static MSOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
 static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
 
-type HidDevice = HidReaderWriter<'static, Driver<'static, USB>, 1, 8>;
+/// Host resumed the bus, input held before the suspend may be stuck on it
+static HOST_RESUMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Merged gamepad state waiting to be sent, only the newest one matters
+static GAMEPAD: Signal<CriticalSectionRawMutex, GamepadReport> = Signal::new();
+
+/// Sends the gamepad state to the host, replacing any not sent yet, and plays it as MIDI
+pub fn send_gamepad(report: GamepadReport) {
+    GAMEPAD.signal(report);
+    midi::play(report);
+}
+
+/// Watches the bus state the HID reports depend on
+struct UsbEvents;
+
+impl Handler for UsbEvents {
+    fn reset(&mut self) {
+        reset_scroll_resolution();
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        if !suspended {
+            HOST_RESUMED.signal(());
+        }
+    }
+}
+
+type BatteryDevice = HidWriter<'static, Driver<'static, USB>, 1>;
 type LoggerDevice = CdcAcmClass<'static, Driver<'static, USB>>;
 
-pub fn config_usb(driver: Driver<'static, USB>) -> (UsbDevice<'static, Driver<'static, USB>>, LoggerDevice, HidDevice, HidDevice, HidDevice) {
+/// Battery strength (0-100%) of the glove, as read by Linux upower and Windows
+const BATTERY_DESCRIPTOR: &[u8] = &[
+    0x05, 0x06,         // Usage Page (Generic Device Controls)
+    0x09, 0x20,         // Usage (Battery Strength)
+    0xA1, 0x01,         // Collection (Application)
+    0x05, 0x06,         //   Usage Page (Generic Device Controls)
+    0x09, 0x20,         //   Usage (Battery Strength)
+    0x15, 0x00,         //   Logical Minimum (0)
+    0x26, 0x64, 0x00,   //   Logical Maximum (100)
+    0x75, 0x08,         //   Report Size (8)
+    0x95, 0x01,         //   Report Count (1)
+    0x81, 0x02,         //   Input (Data, Variable, Absolute)
+    0xC0,               // End Collection
+];
+
+pub fn config_usb(driver: Driver<'static, USB>) -> (UsbDevice<'static, Driver<'static, USB>>, LoggerDevice, CliDevice, TelemetryDevice, HidReports, KeyboardOutput, BatteryDevice, MidiOutput) {
     // Create embassy-usb Config
     let mut config  = embassy_usb::Config::new(0xc0de, 0xcafe);
     config.manufacturer = Some("LosDos");
@@ -45,7 +101,7 @@ pub fn config_usb(driver: Driver<'static, USB>) -> (UsbDevice<'static, Driver<'s
     config.max_packet_size_0 = 64;
 
     // Init static memory
-    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 256]);
+    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 1024]);
     let bos_descriptor = BOS_DESCRIPTOR.init([0; 256]);
     let msos_descriptor = MSOS_DESCRIPTOR.init([0; 256]);
     let control_buf = CONTROL_BUF.init([0; 64]);
@@ -60,84 +116,194 @@ pub fn config_usb(driver: Driver<'static, USB>) -> (UsbDevice<'static, Driver<'s
         control_buf,
     );
 
+    static USB_EVENTS: StaticCell<UsbEvents> = StaticCell::new();
+    builder.handler(USB_EVENTS.init(UsbEvents));
+
     // USB Logger config
     static USB_LOGGER: StaticCell<CdcState<'static>> = StaticCell::new();
     let logger_state = USB_LOGGER.init(CdcState::new());
     let logger = CdcAcmClass::new(&mut builder, logger_state, MAX_PACKET_SIZE.into());
 
-    // Mouse config
-    static MOUSE_STATE: StaticCell<HidState> = StaticCell::new();
-    let mouse_state = MOUSE_STATE.init(HidState::new());
-    let mouse_config = HidConfig {
-        report_descriptor: MouseReport::desc(),
-        request_handler: None,
-        poll_ms: MOUSE_POLL_MS,
-        max_packet_size: 64,
-    };
-    let hid_mouse = HidReaderWriter::<_, 1, 8>::new(&mut builder, mouse_state, mouse_config);
+    // Command line config, a second serial port next to the logger
+    static CLI_STATE: StaticCell<CdcState<'static>> = StaticCell::new();
+    let cli_state = CLI_STATE.init(CdcState::new());
+    let cli = CdcAcmClass::new(&mut builder, cli_state, CLI_PACKET_SIZE);
 
-    // Keyboard config
-    static KEYBOARD_STATE: StaticCell<HidState> = StaticCell::new();
-    let keyboard_state = KEYBOARD_STATE.init(HidState::new());
-    let keyboard_config = HidConfig {
-        report_descriptor: KeyboardReport::desc(),
-        request_handler: None,
-        poll_ms: MOUSE_POLL_MS,
-        max_packet_size: 64,
-    };
-    let hid_keyboard = HidReaderWriter::<_, 1, 8>::new(&mut builder, keyboard_state, keyboard_config);
+    // Telemetry for host apps, a third serial port apart from the log
+    static TELEMETRY_STATE: StaticCell<CdcState<'static>> = StaticCell::new();
+    let telemetry_state = TELEMETRY_STATE.init(CdcState::new());
+    let telemetry = CdcAcmClass::new(&mut builder, telemetry_state, TELEMETRY_PACKET_SIZE);
 
-    // MediaKeyboard config
-    static MEDIA_KEYBOARD_STATE: StaticCell<HidState> = StaticCell::new();
-    let media_state = MEDIA_KEYBOARD_STATE.init(HidState::new());
-    let media_config = HidConfig {
-        report_descriptor: MediaKeyboardReport::desc(),
+    // Mouse, keyboard and media reports, separate or composite depending on the build
+    let (hid_reports, keyboard_output) = HidReports::new(&mut builder);
+
+    // Battery config
+    static BATTERY_STATE: StaticCell<HidState> = StaticCell::new();
+    let battery_state = BATTERY_STATE.init(HidState::new());
+    let battery_config = HidConfig {
+        report_descriptor: BATTERY_DESCRIPTOR,
         request_handler: None,
-        poll_ms: MOUSE_POLL_MS,
-        max_packet_size: 64,
+        poll_ms: BATTERY_POLL_MS,
+        max_packet_size: 8,
     };
-    let hid_media = HidReaderWriter::<_, 1, 8>::new(&mut builder, media_state, media_config);
+    let hid_battery = HidWriter::<_, 1>::new(&mut builder, battery_state, battery_config);
+
+    // MIDI interface, only with the midi feature
+    let midi = MidiOutput::new(&mut builder);
 
     // USB Build
     let usb = builder.build();
 
-    (usb, logger, hid_mouse, hid_keyboard, hid_media)
+    (usb, logger, cli, telemetry, hid_reports, keyboard_output, hid_battery, midi)
+}
+
+
+/// Mouse motion received but not yet sent, wider than a report so none is lost.
+/// Wheel and pan are in hi-res units, reported as whole steps of `scroll_steps`.
+#[derive(Default)]
+struct Motion {
+    x: i32,
+    y: i32,
+    wheel: i32,
+    pan: i32,
+}
+
+impl Motion {
+    fn add(&mut self, mouse: &MouseReport) {
+        self.x = self.x.saturating_add(mouse.x as i32);
+        self.y = self.y.saturating_add(mouse.y as i32);
+        self.wheel = self.wheel.saturating_add(mouse.wheel as i32);
+        self.pan = self.pan.saturating_add(mouse.pan as i32);
+    }
+
+    /// As much of the motion as one report carries, scroll below a step is kept for later
+    fn report(&self, buttons: u8, (wheel_step, pan_step): (i32, i32)) -> MouseReport {
+        let clamp = |delta: i32| delta.clamp(-(i8::MAX as i32), i8::MAX as i32) as i8;
+        MouseReport {
+            buttons,
+            x: clamp(self.x),
+            y: clamp(self.y),
+            wheel: clamp(self.wheel / wheel_step),
+            pan: clamp(self.pan / pan_step),
+        }
+    }
+
+    /// Removes the motion of a report the host accepted
+    fn sent(&mut self, report: &MouseReport, (wheel_step, pan_step): (i32, i32)) {
+        self.x -= report.x as i32;
+        self.y -= report.y as i32;
+        self.wheel -= report.wheel as i32 * wheel_step;
+        self.pan -= report.pan as i32 * pan_step;
+    }
 }
 
 
+/// Writes each interface only when its state changes, and the gamepad as gloves stream it.
+/// Motion queued while the mouse endpoint is busy is summed into fewer reports,
+/// up to the next change of held input.
 #[embassy_executor::task]
-pub async fn hid_usb_controller(mut hid_mouse: HidDevice, mut hid_keyboard: HidDevice, mut hid_media: HidDevice,
-rx_ch: Receiver<'static, CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE>) -> ! {
+pub async fn hid_usb_controller(mut hid_reports: HidReports,
+rx_ch: Receiver<'static, CriticalSectionRawMutex, HidInstruction, HID_QUEUE_SIZE>) -> ! {
+    // Input the host last accepted
+    let mut sent = HeldInput::new();
+    let mut motion = Motion::default();
+    // Instruction that ended the last coalescing, handled next
+    let mut pending: Option<HidInstruction> = None;
     loop {
-        let hid_instruction = rx_ch.receive().await;
-        /*
-        let release_keyboard = KeyboardReport {
-            modifier: 0,
-            reserved: 0,
-            leds: 0,
-            keycodes: [0, 0, 0, 0, 0, 0], // None
-        };
-        */
-        let release_media = MediaKeyboardReport {
-            usage_id: MediaKey::Zero.into()
+        let hid_instruction = match pending.take() {
+            Some(hid_instruction) => hid_instruction,
+            None => match select3(rx_ch.receive(), HOST_RESUMED.wait(), GAMEPAD.wait()).await {
+                Either3::First(hid_instruction) => hid_instruction,
+                Either3::Second(()) if sent != HeldInput::new() => {
+                    log::info!("USB host resumed, releasing held input");
+                    HidInstruction::released()
+                }
+                Either3::Second(()) => continue,
+                Either3::Third(report) => {
+                    if let Err(e) = hid_reports.write_gamepad(&report).await {
+                        log::warn!("Failed to send gamepad report: {:?}", e);
+                    }
+                    continue;
+                }
+            },
         };
+        let held = HeldInput::from_instruction(&hid_instruction);
+        motion.add(&hid_instruction.mouse);
+        while let Ok(next) = rx_ch.try_receive() {
+            if HeldInput::from_instruction(&next) != held {
+                pending = Some(next);
+                break;
+            }
+            motion.add(&next.mouse);
+        }
 
-        if let Err(e) = hid_mouse.write_serialize(&hid_instruction.mouse).await {
-            log::warn!("Failed to send mouse report: {:?}", e);
+        // Motion that does not fit a report goes out in the following ones
+        let steps = scroll_steps();
+        loop {
+            let report = motion.report(held.buttons, steps);
+            let moves = report.x != 0 || report.y != 0 || report.wheel != 0 || report.pan != 0;
+            if held.buttons == sent.buttons && !moves {
+                break;
+            }
+            if let Err(e) = hid_reports.write_mouse(&report).await {
+                log::warn!("Failed to send mouse report: {:?}", e);
+                break;
+            }
+            motion.sent(&report, steps);
+            sent.buttons = held.buttons;
         }
-        if let Err(e) = hid_keyboard.write_serialize(&hid_instruction.keyboard).await {
-            log::warn!("Failed to send keyboard report: {:?}", e);
+        if held.modifier != sent.modifier || held.keycodes != sent.keycodes || held.extra_keys != sent.extra_keys {
+            match hid_reports.write_keyboard(&hid_instruction).await {
+                Ok(()) => {
+                    sent.modifier = held.modifier;
+                    sent.keycodes = held.keycodes;
+                    sent.extra_keys = held.extra_keys;
+                }
+                Err(e) => log::warn!("Failed to send keyboard report: {:?}", e),
+            }
         }
-        /*
-        if let Err(e) = hid_keyboard.write_serialize(&release_keyboard).await {
-            log::warn!("Failed to release keyboard report: {:?}", e);
+        // Consumer and system keys stay pressed until the gloves release them
+        if held.consumer != sent.consumer {
+            match hid_reports.write_media(&MediaKeyboardReport { usage_id: held.consumer }).await {
+                Ok(()) => sent.consumer = held.consumer,
+                Err(e) => log::warn!("Failed to send media report: {:?}", e),
+            }
         }
-        */
-        if let Err(e) = hid_media.write_serialize(&hid_instruction.media).await {
-            log::warn!("Failed to send keyboard report: {:?}", e);
+        if held.system != sent.system {
+            match hid_reports.write_system(held.system).await {
+                Ok(()) => sent.system = held.system,
+                Err(e) => log::warn!("Failed to send system report: {:?}", e),
+            }
         }
-        if let Err(e) = hid_media.write_serialize(&release_media).await {
-            log::warn!("Failed to release keyboard report: {:?}", e);
+    }
+}
+
+
+/// Reads the LED output reports the host sends through the keyboard OUT endpoint
+#[embassy_executor::task]
+pub async fn keyboard_output_task(keyboard_output: KeyboardOutput) -> ! {
+    keyboard_output.run().await
+}
+
+
+/// Forwards the keyboard LEDs to the gloves, which show them as feedback
+#[embassy_executor::task]
+pub async fn keyboard_leds_task(relay: &'static GloveRelay) -> ! {
+    loop {
+        let leds = keyboard_leds().await;
+        log::info!("Host keyboard LEDs: {:#05b}", leds);
+        relay.keyboard_leds(leds).await;
+    }
+}
+
+
+#[embassy_executor::task]
+pub async fn hid_battery_reporter(mut hid_battery: BatteryDevice,
+battery_signal: &'static Signal<CriticalSectionRawMutex, BatteryStatus>) -> ! {
+    loop {
+        let status = battery_signal.wait().await;
+        if let Err(e) = hid_battery.write(&[status.percent.min(100)]).await {
+            log::warn!("Failed to send battery report: {:?}", e);
         }
     }
 }
@@ -0,0 +1,685 @@
+use embassy_rp::{
+    peripherals::USB,
+    usb::Driver,
+};
+use embassy_usb::{
+    Builder,
+    class::hid::{Config as HidConfig, State as HidState, RequestHandler, ReportId},
+    control::OutResponse,
+    driver::EndpointError,
+};
+use embassy_sync::{
+    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use portable_atomic::{AtomicU8, Ordering};
+use usbd_hid::descriptor::{MouseReport, MediaKeyboardReport};
+use static_cell::StaticCell;
+
+use shared::{
+    definitions::{MOUSE_POLL_MS, SCROLL_MULTIPLIER},
+    custom_hid::{HidInstruction, GamepadReport, GAMEPAD_FINGERS},
+};
+
+// Layout of the input reports on the bus, picked at build time.
+// Both expose the same writes so the HID controller does not depend on it.
+
+#[cfg(not(feature = "composite-hid"))]
+pub use separate::*;
+#[cfg(feature = "composite-hid")]
+pub use composite::*;
+
+#[cfg(all(feature = "composite-hid", feature = "nkro"))]
+compile_error!("the NKRO keyboard is negotiated through the boot protocol, which composite-hid lacks");
+
+/// Resolution Multiplier feature set by the host, 2 bits for the wheel then 2 for AC Pan
+static RESOLUTION: AtomicU8 = AtomicU8::new(0);
+const WHEEL_HI_RES: u8  = 0b01;
+const PAN_HI_RES: u8    = 0b0100;
+
+/// Hi-res scroll units in one reported wheel and pan step.
+/// Until the host enables the multiplier it reads every step as a whole detent.
+pub fn scroll_steps() -> (i32, i32) {
+    let resolution = RESOLUTION.load(Ordering::Relaxed);
+    let step = |enabled: u8| match resolution & enabled != 0 {
+        true => 1,
+        false => SCROLL_MULTIPLIER as i32,
+    };
+    (step(WHEEL_HI_RES), step(PAN_HI_RES))
+}
+
+/// Back to whole detents, as the host expects after a bus reset
+pub fn reset_scroll_resolution() {
+    RESOLUTION.store(0, Ordering::Relaxed);
+}
+
+/// Keyboard LEDs set by the host, through the OUT endpoint or a SET_REPORT request
+static KEYBOARD_LEDS: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+/// Last keyboard LEDs set by the host, for gloves connecting afterwards
+static CURRENT_LEDS: AtomicU8 = AtomicU8::new(0);
+
+/// Waits for the host to set the keyboard LEDs
+pub async fn keyboard_leds() -> u8 {
+    KEYBOARD_LEDS.wait().await
+}
+
+/// Keyboard LEDs the host set last
+pub fn current_keyboard_leds() -> u8 {
+    CURRENT_LEDS.load(Ordering::Relaxed)
+}
+
+/// Answers the reports the host sends to an interface: the Resolution
+/// Multiplier feature of the mouse and the LED output of the keyboard
+#[derive(Clone, Copy)]
+struct HostReports {
+    /// Report IDs of the mouse and keyboard on the interface, 0 on an interface without IDs
+    mouse: Option<u8>,
+    keyboard: Option<u8>,
+}
+
+/// Report data past its ID, reports sent with an ID start with it
+fn payload(id: u8, data: &[u8]) -> Option<u8> {
+    match id {
+        0 => data.first().copied(),
+        _ => data.get(1).copied(),
+    }
+}
+
+impl RequestHandler for HostReports {
+    fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        let resolution = RESOLUTION.load(Ordering::Relaxed);
+        let report = match id {
+            ReportId::Feature(0) if self.mouse == Some(0) => &[resolution][..],
+            ReportId::Feature(id) if self.mouse == Some(id) => &[id, resolution][..],
+            _ => return None,
+        };
+        let target = buf.get_mut(..report.len())?;
+        target.copy_from_slice(report);
+        Some(report.len())
+    }
+
+    fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
+        match id {
+            ReportId::Feature(id) if self.mouse == Some(id) => {
+                let Some(resolution) = payload(id, data) else { return OutResponse::Rejected };
+                RESOLUTION.store(resolution, Ordering::Relaxed);
+                log::info!("Host set the scroll resolution to {:#04b}", resolution);
+                OutResponse::Accepted
+            }
+            ReportId::Out(id) if self.keyboard == Some(id) => {
+                let Some(leds) = payload(id, data) else { return OutResponse::Rejected };
+                CURRENT_LEDS.store(leds, Ordering::Relaxed);
+                KEYBOARD_LEDS.signal(leds);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+}
+
+/// Output reports the host sends through the interrupt OUT endpoint
+pub struct HostOutput<R> {
+    reader: R,
+    handler: HostReports,
+}
+
+/// Buttons, three orientation axes and a byte per finger
+const GAMEPAD_REPORT_SIZE: usize = 2 + 3 * 2 + GAMEPAD_FINGERS;
+
+/// Gamepad input report, in the little endian order of the descriptor
+fn gamepad_payload(report: &GamepadReport) -> [u8; GAMEPAD_REPORT_SIZE] {
+    let mut data = [0; GAMEPAD_REPORT_SIZE];
+    data[..2].copy_from_slice(&report.buttons.to_le_bytes());
+    for (chunk, axis) in data[2..8].chunks_exact_mut(2).zip(report.orientation) {
+        chunk.copy_from_slice(&axis.to_le_bytes());
+    }
+    data[8..].copy_from_slice(&report.fingers);
+    data
+}
+
+
+/// Mouse, keyboard, and consumer and system keys on an interface and endpoint each
+#[cfg(not(feature = "composite-hid"))]
+mod separate {
+    use embassy_usb::class::hid::HidWriter;
+    use shared::custom_hid::KEY_BITMAP_SIZE;
+    use crate::boot_hid::{self, BootHid, BootHidReader, BootDevice, BOOT_KEYBOARD_REPORT_SIZE};
+    use super::*;
+
+    /// The boot report, followed by a key bitmap when NKRO is built in
+    const KEYBOARD_REPORT_SIZE: usize = match cfg!(feature = "nkro") {
+        true => BOOT_KEYBOARD_REPORT_SIZE + KEY_BITMAP_SIZE,
+        false => BOOT_KEYBOARD_REPORT_SIZE,
+    };
+
+    /// Boot keyboard followed by one bit per key. Boot hosts read the six keycodes,
+    /// report protocol hosts get them empty and every key in the bitmap instead.
+    #[cfg(feature = "nkro")]
+    const KEYBOARD_DESCRIPTOR: &[u8] = &[
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x06,         // Usage (Keyboard)
+        0xA1, 0x01,         // Collection (Application)
+        0x05, 0x07,         //   Usage Page (Keyboard)
+        0x19, 0xE0,         //   Usage Minimum (Left Control)
+        0x29, 0xE7,         //   Usage Maximum (Right GUI)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x25, 0x01,         //   Logical Maximum (1)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x08,         //   Report Count (8)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x01,         //   Input (Constant)
+        0x05, 0x08,         //   Usage Page (LEDs)
+        0x19, 0x01,         //   Usage Minimum (Num Lock)
+        0x29, 0x05,         //   Usage Maximum (Kana)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x05,         //   Report Count (5)
+        0x91, 0x02,         //   Output (Data, Variable, Absolute)
+        0x75, 0x03,         //   Report Size (3)
+        0x95, 0x01,         //   Report Count (1)
+        0x91, 0x01,         //   Output (Constant)
+        0x05, 0x07,         //   Usage Page (Keyboard)
+        0x19, 0x00,         //   Usage Minimum (0)
+        0x2A, 0xFF, 0x00,   //   Usage Maximum (255)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x06,         //   Report Count (6)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0x19, 0x00,         //   Usage Minimum (0)
+        0x29, 0x7F,         //   Usage Maximum (127)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x25, 0x01,         //   Logical Maximum (1)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x80,         //   Report Count (128)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0xC0,               // End Collection
+    ];
+
+    #[cfg(feature = "nkro")]
+    fn keyboard_descriptor() -> &'static [u8] {
+        KEYBOARD_DESCRIPTOR
+    }
+
+    #[cfg(not(feature = "nkro"))]
+    fn keyboard_descriptor() -> &'static [u8] {
+        use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
+        KeyboardReport::desc()
+    }
+
+    const CONSUMER_ID: u8   = 1;
+    const SYSTEM_ID: u8     = 2;
+    /// Consumer usage plus its ID
+    const CONTROL_REPORT_SIZE: usize = 3;
+
+    /// Sixteen buttons, the glove orientation and the bend of each finger
+    const GAMEPAD_DESCRIPTOR: &[u8] = &[
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x05,         // Usage (Game Pad)
+        0xA1, 0x01,         // Collection (Application)
+        0x05, 0x09,         //   Usage Page (Button)
+        0x19, 0x01,         //   Usage Minimum (1)
+        0x29, 0x10,         //   Usage Maximum (16)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x25, 0x01,         //   Logical Maximum (1)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x10,         //   Report Count (16)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x05, 0x01,         //   Usage Page (Generic Desktop)
+        0x09, 0x30,         //   Usage (X), roll
+        0x09, 0x31,         //   Usage (Y), pitch
+        0x09, 0x32,         //   Usage (Z), yaw
+        0x16, 0x01, 0x80,   //   Logical Minimum (-32767)
+        0x26, 0xFF, 0x7F,   //   Logical Maximum (32767)
+        0x75, 0x10,         //   Report Size (16)
+        0x95, 0x03,         //   Report Count (3)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x09, 0x33,         //   Usage (Rx), thumb
+        0x09, 0x34,         //   Usage (Ry), index
+        0x09, 0x35,         //   Usage (Rz), middle
+        0x09, 0x36,         //   Usage (Slider), ring
+        0x09, 0x37,         //   Usage (Dial), pinky
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x05,         //   Report Count (5)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0xC0,               // End Collection
+    ];
+
+    /// Consumer keys and System Control, held until a report releases them
+    const CONTROL_DESCRIPTOR: &[u8] = &[
+        0x05, 0x0C,         // Usage Page (Consumer)
+        0x09, 0x01,         // Usage (Consumer Control)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, CONSUMER_ID,  //   Report ID
+        0x19, 0x00,         //   Usage Minimum (0)
+        0x2A, 0xFF, 0x03,   //   Usage Maximum (1023)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x03,   //   Logical Maximum (1023)
+        0x75, 0x10,         //   Report Size (16)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0xC0,               // End Collection
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x80,         // Usage (System Control)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, SYSTEM_ID,    //   Report ID
+        0x19, 0x81,         //   Usage Minimum (System Power Down)
+        0x29, 0x83,         //   Usage Maximum (System Wake Up)
+        0x16, 0x81, 0x00,   //   Logical Minimum (0x81)
+        0x26, 0x83, 0x00,   //   Logical Maximum (0x83)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0xC0,               // End Collection
+    ];
+
+    /// Buttons, X, Y, and wheel and AC Pan in hi-res units once the host enables them
+    const MOUSE_DESCRIPTOR: &[u8] = &[
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x02,         // Usage (Mouse)
+        0xA1, 0x01,         // Collection (Application)
+        0x09, 0x01,         //   Usage (Pointer)
+        0xA1, 0x00,         //   Collection (Physical)
+        0x05, 0x09,         //     Usage Page (Button)
+        0x19, 0x01,         //     Usage Minimum (1)
+        0x29, 0x08,         //     Usage Maximum (8)
+        0x15, 0x00,         //     Logical Minimum (0)
+        0x25, 0x01,         //     Logical Maximum (1)
+        0x75, 0x01,         //     Report Size (1)
+        0x95, 0x08,         //     Report Count (8)
+        0x81, 0x02,         //     Input (Data, Variable, Absolute)
+        0x05, 0x01,         //     Usage Page (Generic Desktop)
+        0x09, 0x30,         //     Usage (X)
+        0x09, 0x31,         //     Usage (Y)
+        0x15, 0x81,         //     Logical Minimum (-127)
+        0x25, 0x7F,         //     Logical Maximum (127)
+        0x75, 0x08,         //     Report Size (8)
+        0x95, 0x02,         //     Report Count (2)
+        0x81, 0x06,         //     Input (Data, Variable, Relative)
+        0xA1, 0x02,         //     Collection (Logical)
+        0x09, 0x48,         //       Usage (Resolution Multiplier)
+        0x15, 0x00,         //       Logical Minimum (0)
+        0x25, 0x01,         //       Logical Maximum (1)
+        0x35, 0x01,         //       Physical Minimum (1)
+        0x45, SCROLL_MULTIPLIER, // Physical Maximum
+        0x75, 0x02,         //       Report Size (2)
+        0x95, 0x01,         //       Report Count (1)
+        0xB1, 0x02,         //       Feature (Data, Variable, Absolute)
+        0x35, 0x00,         //       Physical Minimum (0)
+        0x45, 0x00,         //       Physical Maximum (0)
+        0x09, 0x38,         //       Usage (Wheel)
+        0x15, 0x81,         //       Logical Minimum (-127)
+        0x25, 0x7F,         //       Logical Maximum (127)
+        0x75, 0x08,         //       Report Size (8)
+        0x95, 0x01,         //       Report Count (1)
+        0x81, 0x06,         //       Input (Data, Variable, Relative)
+        0xC0,               //     End Collection
+        0xA1, 0x02,         //     Collection (Logical)
+        0x09, 0x48,         //       Usage (Resolution Multiplier)
+        0x15, 0x00,         //       Logical Minimum (0)
+        0x25, 0x01,         //       Logical Maximum (1)
+        0x35, 0x01,         //       Physical Minimum (1)
+        0x45, SCROLL_MULTIPLIER, // Physical Maximum
+        0x75, 0x02,         //       Report Size (2)
+        0x95, 0x01,         //       Report Count (1)
+        0xB1, 0x02,         //       Feature (Data, Variable, Absolute)
+        0x35, 0x00,         //       Physical Minimum (0)
+        0x45, 0x00,         //       Physical Maximum (0)
+        0x05, 0x0C,         //       Usage Page (Consumer)
+        0x0A, 0x38, 0x02,   //       Usage (AC Pan)
+        0x15, 0x81,         //       Logical Minimum (-127)
+        0x25, 0x7F,         //       Logical Maximum (127)
+        0x75, 0x08,         //       Report Size (8)
+        0x95, 0x01,         //       Report Count (1)
+        0x81, 0x06,         //       Input (Data, Variable, Relative)
+        0xC0,               //     End Collection
+        0x75, 0x04,         //     Report Size (4)
+        0x95, 0x01,         //     Report Count (1)
+        0xB1, 0x01,         //     Feature (Constant)
+        0xC0,               //   End Collection
+        0xC0,               // End Collection
+    ];
+
+    /// Keyboard LEDs read from the boot keyboard OUT endpoint
+    pub type KeyboardOutput = HostOutput<BootHidReader>;
+
+    impl KeyboardOutput {
+        pub async fn run(mut self) -> ! {
+            self.reader.run(&mut self.handler).await
+        }
+    }
+
+    /// Mouse and keyboard also answer in boot protocol, the report layouts start like the boot ones
+    pub struct HidReports {
+        mouse: BootHid,
+        keyboard: BootHid,
+        control: HidWriter<'static, Driver<'static, USB>, CONTROL_REPORT_SIZE>,
+        gamepad: HidWriter<'static, Driver<'static, USB>, GAMEPAD_REPORT_SIZE>,
+    }
+
+    fn config(report_descriptor: &'static [u8], request_handler: Option<&'static mut dyn RequestHandler>) -> HidConfig<'static> {
+        HidConfig {
+            report_descriptor,
+            request_handler,
+            poll_ms: MOUSE_POLL_MS,
+            max_packet_size: 64,
+        }
+    }
+
+    impl HidReports {
+        pub fn new(builder: &mut Builder<'static, Driver<'static, USB>>) -> (Self, KeyboardOutput) {
+            static MOUSE_STATE: boot_hid::State = boot_hid::State::new();
+            static KEYBOARD_STATE: boot_hid::State = boot_hid::State::new();
+            static CONTROL_STATE: StaticCell<HidState> = StaticCell::new();
+            static GAMEPAD_STATE: StaticCell<HidState> = StaticCell::new();
+            static MOUSE_HANDLER: StaticCell<HostReports> = StaticCell::new();
+            static KEYBOARD_HANDLER: StaticCell<HostReports> = StaticCell::new();
+            let mouse_handler = HostReports { mouse: Some(0), keyboard: None };
+            let keyboard_handler = HostReports { mouse: None, keyboard: Some(0) };
+
+            let mouse_config = config(MOUSE_DESCRIPTOR, Some(MOUSE_HANDLER.init(mouse_handler)));
+            let (mouse, _) = BootHid::new(builder, &MOUSE_STATE, BootDevice::Mouse, mouse_config);
+            let keyboard_config = config(keyboard_descriptor(), Some(KEYBOARD_HANDLER.init(keyboard_handler)));
+            let (keyboard, reader) = BootHid::new(builder, &KEYBOARD_STATE, BootDevice::Keyboard, keyboard_config);
+            let control_config = config(CONTROL_DESCRIPTOR, None);
+            let control = HidWriter::new(builder, CONTROL_STATE.init(HidState::new()), control_config);
+            let gamepad_config = config(GAMEPAD_DESCRIPTOR, None);
+            let gamepad = HidWriter::new(builder, GAMEPAD_STATE.init(HidState::new()), gamepad_config);
+
+            let reader = reader.expect("boot keyboards have an OUT endpoint");
+            (HidReports { mouse, keyboard, control, gamepad }, HostOutput { reader, handler: keyboard_handler })
+        }
+
+        pub async fn write_mouse(&mut self, report: &MouseReport) -> Result<(), EndpointError> {
+            self.mouse.write(&[report.buttons, report.x as u8, report.y as u8, report.wheel as u8, report.pan as u8]).await
+        }
+
+        /// Extra keys only reach the host as NKRO, a 6KRO report keeps the first six
+        pub async fn write_keyboard(&mut self, hid_instruction: &HidInstruction) -> Result<(), EndpointError> {
+            let report = &hid_instruction.keyboard;
+            let mut data = [0; KEYBOARD_REPORT_SIZE];
+            data[0] = report.modifier;
+            if cfg!(feature = "nkro") && !self.keyboard.boot_protocol() {
+                data[BOOT_KEYBOARD_REPORT_SIZE..].copy_from_slice(&hid_instruction.keys().0);
+            } else {
+                data[2..BOOT_KEYBOARD_REPORT_SIZE].copy_from_slice(&report.keycodes);
+            }
+            self.keyboard.write(&data).await
+        }
+
+        pub async fn write_media(&mut self, report: &MediaKeyboardReport) -> Result<(), EndpointError> {
+            let [low, high] = report.usage_id.to_le_bytes();
+            self.control.write(&[CONSUMER_ID, low, high]).await
+        }
+
+        /// Presses a System Control usage (0x81 power down, 0x82 sleep, 0x83 wake up), 0 releases it
+        pub async fn write_system(&mut self, usage: u8) -> Result<(), EndpointError> {
+            self.control.write(&[SYSTEM_ID, usage]).await
+        }
+
+        pub async fn write_gamepad(&mut self, report: &GamepadReport) -> Result<(), EndpointError> {
+            self.gamepad.write(&gamepad_payload(report)).await
+        }
+    }
+}
+
+
+/// Every report on a single interface and endpoint, told apart by report ID
+#[cfg(feature = "composite-hid")]
+mod composite {
+    use embassy_usb::class::hid::{HidReader, HidReaderWriter, HidWriter};
+    use super::*;
+
+    const MOUSE_ID: u8      = 1;
+    const KEYBOARD_ID: u8   = 2;
+    const CONSUMER_ID: u8   = 3;
+    const SYSTEM_ID: u8     = 4;
+    const VENDOR_ID: u8     = 5;
+    const GAMEPAD_ID: u8    = 6;
+    /// Payload of the vendor defined report
+    pub const VENDOR_REPORT_SIZE: usize = 8;
+
+    /// Largest report, the gamepad, plus its ID
+    const REPORT_SIZE: usize = 1 + GAMEPAD_REPORT_SIZE;
+
+    const COMPOSITE_DESCRIPTOR: &[u8] = &[
+        // Mouse
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x02,         // Usage (Mouse)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, MOUSE_ID,     //   Report ID
+        0x09, 0x01,         //   Usage (Pointer)
+        0xA1, 0x00,         //   Collection (Physical)
+        0x05, 0x09,         //     Usage Page (Button)
+        0x19, 0x01,         //     Usage Minimum (1)
+        0x29, 0x08,         //     Usage Maximum (8)
+        0x15, 0x00,         //     Logical Minimum (0)
+        0x25, 0x01,         //     Logical Maximum (1)
+        0x75, 0x01,         //     Report Size (1)
+        0x95, 0x08,         //     Report Count (8)
+        0x81, 0x02,         //     Input (Data, Variable, Absolute)
+        0x05, 0x01,         //     Usage Page (Generic Desktop)
+        0x09, 0x30,         //     Usage (X)
+        0x09, 0x31,         //     Usage (Y)
+        0x15, 0x81,         //     Logical Minimum (-127)
+        0x25, 0x7F,         //     Logical Maximum (127)
+        0x75, 0x08,         //     Report Size (8)
+        0x95, 0x02,         //     Report Count (2)
+        0x81, 0x06,         //     Input (Data, Variable, Relative)
+        0xA1, 0x02,         //     Collection (Logical)
+        0x09, 0x48,         //       Usage (Resolution Multiplier)
+        0x15, 0x00,         //       Logical Minimum (0)
+        0x25, 0x01,         //       Logical Maximum (1)
+        0x35, 0x01,         //       Physical Minimum (1)
+        0x45, SCROLL_MULTIPLIER, // Physical Maximum
+        0x75, 0x02,         //       Report Size (2)
+        0x95, 0x01,         //       Report Count (1)
+        0xB1, 0x02,         //       Feature (Data, Variable, Absolute)
+        0x35, 0x00,         //       Physical Minimum (0)
+        0x45, 0x00,         //       Physical Maximum (0)
+        0x09, 0x38,         //       Usage (Wheel)
+        0x15, 0x81,         //       Logical Minimum (-127)
+        0x25, 0x7F,         //       Logical Maximum (127)
+        0x75, 0x08,         //       Report Size (8)
+        0x95, 0x01,         //       Report Count (1)
+        0x81, 0x06,         //       Input (Data, Variable, Relative)
+        0xC0,               //     End Collection
+        0xA1, 0x02,         //     Collection (Logical)
+        0x09, 0x48,         //       Usage (Resolution Multiplier)
+        0x15, 0x00,         //       Logical Minimum (0)
+        0x25, 0x01,         //       Logical Maximum (1)
+        0x35, 0x01,         //       Physical Minimum (1)
+        0x45, SCROLL_MULTIPLIER, // Physical Maximum
+        0x75, 0x02,         //       Report Size (2)
+        0x95, 0x01,         //       Report Count (1)
+        0xB1, 0x02,         //       Feature (Data, Variable, Absolute)
+        0x35, 0x00,         //       Physical Minimum (0)
+        0x45, 0x00,         //       Physical Maximum (0)
+        0x05, 0x0C,         //       Usage Page (Consumer)
+        0x0A, 0x38, 0x02,   //       Usage (AC Pan)
+        0x15, 0x81,         //       Logical Minimum (-127)
+        0x25, 0x7F,         //       Logical Maximum (127)
+        0x75, 0x08,         //       Report Size (8)
+        0x95, 0x01,         //       Report Count (1)
+        0x81, 0x06,         //       Input (Data, Variable, Relative)
+        0xC0,               //     End Collection
+        0x75, 0x04,         //     Report Size (4)
+        0x95, 0x01,         //     Report Count (1)
+        0xB1, 0x01,         //     Feature (Constant)
+        0xC0,               //   End Collection
+        0xC0,               // End Collection
+        // Keyboard
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x06,         // Usage (Keyboard)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, KEYBOARD_ID,  //   Report ID
+        0x05, 0x07,         //   Usage Page (Keyboard)
+        0x19, 0xE0,         //   Usage Minimum (Left Control)
+        0x29, 0xE7,         //   Usage Maximum (Right GUI)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x25, 0x01,         //   Logical Maximum (1)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x08,         //   Report Count (8)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x01,         //   Input (Constant)
+        0x05, 0x08,         //   Usage Page (LEDs)
+        0x19, 0x01,         //   Usage Minimum (Num Lock)
+        0x29, 0x05,         //   Usage Maximum (Kana)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x05,         //   Report Count (5)
+        0x91, 0x02,         //   Output (Data, Variable, Absolute)
+        0x75, 0x03,         //   Report Size (3)
+        0x95, 0x01,         //   Report Count (1)
+        0x91, 0x01,         //   Output (Constant)
+        0x05, 0x07,         //   Usage Page (Keyboard)
+        0x19, 0x00,         //   Usage Minimum (0)
+        0x2A, 0xFF, 0x00,   //   Usage Maximum (255)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x06,         //   Report Count (6)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0xC0,               // End Collection
+        // Consumer control
+        0x05, 0x0C,         // Usage Page (Consumer)
+        0x09, 0x01,         // Usage (Consumer Control)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, CONSUMER_ID,  //   Report ID
+        0x19, 0x00,         //   Usage Minimum (0)
+        0x2A, 0xFF, 0x03,   //   Usage Maximum (1023)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x03,   //   Logical Maximum (1023)
+        0x75, 0x10,         //   Report Size (16)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0xC0,               // End Collection
+        // System control
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x80,         // Usage (System Control)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, SYSTEM_ID,    //   Report ID
+        0x19, 0x81,         //   Usage Minimum (System Power Down)
+        0x29, 0x83,         //   Usage Maximum (System Wake Up)
+        0x16, 0x81, 0x00,   //   Logical Minimum (0x81)
+        0x26, 0x83, 0x00,   //   Logical Maximum (0x83)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x01,         //   Report Count (1)
+        0x81, 0x00,         //   Input (Data, Array, Absolute)
+        0xC0,               // End Collection
+        // Vendor
+        0x06, 0x00, 0xFF,   // Usage Page (Vendor Defined 0xFF00)
+        0x09, 0x01,         // Usage (1)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, VENDOR_ID,    //   Report ID
+        0x09, 0x01,         //   Usage (1)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, VENDOR_REPORT_SIZE as u8, //   Report Count (8)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0xC0,               // End Collection
+        // Gamepad
+        0x05, 0x01,         // Usage Page (Generic Desktop)
+        0x09, 0x05,         // Usage (Game Pad)
+        0xA1, 0x01,         // Collection (Application)
+        0x85, GAMEPAD_ID,   //   Report ID
+        0x05, 0x09,         //   Usage Page (Button)
+        0x19, 0x01,         //   Usage Minimum (1)
+        0x29, 0x10,         //   Usage Maximum (16)
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x25, 0x01,         //   Logical Maximum (1)
+        0x75, 0x01,         //   Report Size (1)
+        0x95, 0x10,         //   Report Count (16)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x05, 0x01,         //   Usage Page (Generic Desktop)
+        0x09, 0x30,         //   Usage (X), roll
+        0x09, 0x31,         //   Usage (Y), pitch
+        0x09, 0x32,         //   Usage (Z), yaw
+        0x16, 0x01, 0x80,   //   Logical Minimum (-32767)
+        0x26, 0xFF, 0x7F,   //   Logical Maximum (32767)
+        0x75, 0x10,         //   Report Size (16)
+        0x95, 0x03,         //   Report Count (3)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0x09, 0x33,         //   Usage (Rx), thumb
+        0x09, 0x34,         //   Usage (Ry), index
+        0x09, 0x35,         //   Usage (Rz), middle
+        0x09, 0x36,         //   Usage (Slider), ring
+        0x09, 0x37,         //   Usage (Dial), pinky
+        0x15, 0x00,         //   Logical Minimum (0)
+        0x26, 0xFF, 0x00,   //   Logical Maximum (255)
+        0x75, 0x08,         //   Report Size (8)
+        0x95, 0x05,         //   Report Count (5)
+        0x81, 0x02,         //   Input (Data, Variable, Absolute)
+        0xC0,               // End Collection
+    ];
+
+    /// Keyboard LEDs, a byte after the report ID
+    pub type KeyboardOutput = HostOutput<HidReader<'static, Driver<'static, USB>, 2>>;
+
+    impl KeyboardOutput {
+        pub async fn run(mut self) -> ! {
+            self.reader.run(true, &mut self.handler).await
+        }
+    }
+
+    pub struct HidReports {
+        hid: HidWriter<'static, Driver<'static, USB>, REPORT_SIZE>,
+    }
+
+    impl HidReports {
+        pub fn new(builder: &mut Builder<'static, Driver<'static, USB>>) -> (Self, KeyboardOutput) {
+            static COMPOSITE_STATE: StaticCell<HidState> = StaticCell::new();
+            static COMPOSITE_HANDLER: StaticCell<HostReports> = StaticCell::new();
+            let handler = HostReports { mouse: Some(MOUSE_ID), keyboard: Some(KEYBOARD_ID) };
+            let config = HidConfig {
+                report_descriptor: COMPOSITE_DESCRIPTOR,
+                request_handler: Some(COMPOSITE_HANDLER.init(handler)),
+                poll_ms: MOUSE_POLL_MS,
+                max_packet_size: 64,
+            };
+            let hid = HidReaderWriter::<_, 2, REPORT_SIZE>::new(builder, COMPOSITE_STATE.init(HidState::new()), config);
+            let (reader, hid) = hid.split();
+            (HidReports { hid }, HostOutput { reader, handler })
+        }
+
+        pub async fn write_mouse(&mut self, report: &MouseReport) -> Result<(), EndpointError> {
+            self.hid.write(&[MOUSE_ID, report.buttons, report.x as u8, report.y as u8, report.wheel as u8, report.pan as u8]).await
+        }
+
+        /// Keeps the first six keys, the extra ones need the NKRO keyboard
+        pub async fn write_keyboard(&mut self, hid_instruction: &HidInstruction) -> Result<(), EndpointError> {
+            let report = &hid_instruction.keyboard;
+            let mut data = [KEYBOARD_ID, report.modifier, 0, 0, 0, 0, 0, 0, 0];
+            data[3..].copy_from_slice(&report.keycodes);
+            self.hid.write(&data).await
+        }
+
+        pub async fn write_media(&mut self, report: &MediaKeyboardReport) -> Result<(), EndpointError> {
+            let [low, high] = report.usage_id.to_le_bytes();
+            self.hid.write(&[CONSUMER_ID, low, high]).await
+        }
+
+        /// Presses a System Control usage (0x81 power down, 0x82 sleep, 0x83 wake up), 0 releases it
+        pub async fn write_system(&mut self, usage: u8) -> Result<(), EndpointError> {
+            self.hid.write(&[SYSTEM_ID, usage]).await
+        }
+
+        /// Sends raw bytes to host apps reading the vendor defined (0xFF00) report
+        pub async fn write_vendor(&mut self, payload: &[u8; VENDOR_REPORT_SIZE]) -> Result<(), EndpointError> {
+            let mut data = [VENDOR_ID; 1 + VENDOR_REPORT_SIZE];
+            data[1..].copy_from_slice(payload);
+            self.hid.write(&data).await
+        }
+
+        pub async fn write_gamepad(&mut self, report: &GamepadReport) -> Result<(), EndpointError> {
+            let mut data = [GAMEPAD_ID; 1 + GAMEPAD_REPORT_SIZE];
+            data[1..].copy_from_slice(&gamepad_payload(report));
+            self.hid.write(&data).await
+        }
+    }
+}
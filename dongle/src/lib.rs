@@ -3,3 +3,13 @@
 
 pub mod tcp_server;
 pub mod hid;
+pub mod hid_reports;
+pub mod boot_hid;
+pub mod status;
+pub mod cli;
+pub mod relay;
+pub mod discovery;
+pub mod merge;
+pub mod midi;
+pub mod telemetry;
+pub mod pairing;
@@ -2,6 +2,7 @@
 #![no_main]
 #![allow(async_fn_in_trait)]
 
+use core::cell::RefCell;
 use defmt::*;
 use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
 use embassy_executor::Spawner;
@@ -11,24 +12,37 @@ use embassy_rp::{
     peripherals::{DMA_CH0, PIO0, USB},
     pio::{InterruptHandler, Pio},
     usb::{self, Driver},
+    flash::Flash,
 };
 use embassy_usb::class::cdc_acm::CdcAcmClass;
 use embassy_sync::{
     channel::Channel,
-    blocking_mutex::raw::CriticalSectionRawMutex,
+    signal::Signal,
+    mutex::Mutex,
+    blocking_mutex::{Mutex as BlockingMutex, raw::CriticalSectionRawMutex},
 };
 
 use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
 use shared::{
-    definitions::CHANNEL_SIZE,
-    custom_hid::HidInstruction
+    definitions::HID_QUEUE_SIZE,
+    custom_hid::HidInstruction,
+    protocol::BatteryStatus,
+    config::SharedSettings,
+    storage::{SharedStore, mount_store},
 };
 
 use dongle::{
     tcp_server::{network_config, tcp_server_task,},
-    hid::{config_usb, hid_usb_controller}
+    hid::{config_usb, hid_usb_controller, hid_battery_reporter, keyboard_output_task, keyboard_leds_task},
+    status::{SharedStatus, new_status},
+    relay::GloveRelay,
+    cli::cli_task,
+    discovery::beacon_task,
+    midi::midi_task,
+    telemetry::telemetry_task,
+    pairing::pairing_task,
 };
 
 
@@ -64,13 +78,29 @@ async fn main(spawner: Spawner) {
 
     // Config USB port
     let driver = Driver::new(p.USB, Irqs);
-    let (usb, logger, hid_mouse, hid_keyboard, hid_media) = config_usb(driver);
+    let (usb, logger, cli, telemetry, hid_reports, keyboard_output, hid_battery, midi) = config_usb(driver);
 
     // Launch usb and usb logger tasks
     unwrap!(spawner.spawn(usb_task(usb)));
     unwrap!(spawner.spawn(logger_task(logger)));
     log::info!("USB Logger set up");
 
+    // Load persisted settings
+    let mut store = mount_store(Flash::new_blocking(p.FLASH));
+    static SETTINGS: StaticCell<SharedSettings> = StaticCell::new();
+    let settings = SETTINGS.init(BlockingMutex::new(RefCell::new(store.load())));
+    static STORE: StaticCell<SharedStore> = StaticCell::new();
+    let store = STORE.init(Mutex::new(store));
+
+    // Launch command line on the second serial port
+    static STATUS: SharedStatus = new_status();
+    static RELAY: GloveRelay = GloveRelay::new(&STATUS);
+    unwrap!(spawner.spawn(cli_task(cli, settings, store, &STATUS, &RELAY)));
+    unwrap!(spawner.spawn(pairing_task(settings, store, &STATUS)));
+
+    // Telemetry for host apps on the third serial port
+    unwrap!(spawner.spawn(telemetry_task(telemetry)));
+
     // cyw43 wifi chip init
     let fw = include_bytes!("../../shared/cyw43-firmware/43439A0.bin");
     let clm = include_bytes!("../../shared/cyw43-firmware/43439A0_clm.bin");
@@ -99,15 +129,23 @@ async fn main(spawner: Spawner) {
         .set_power_management(cyw43::PowerManagementMode::None)
         .await;
 
-    let (stack, runner) = network_config(net_device);
+    let dongle_ip = settings.lock(|settings| settings.borrow().network.dongle_ip);
+    let (stack, runner) = network_config(net_device, dongle_ip);
     unwrap!(spawner.spawn(net_task(runner)));
+    unwrap!(spawner.spawn(beacon_task(stack)));
 
-    static CHANNEL: Channel<CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE> = Channel::new();
+    static CHANNEL: Channel<CriticalSectionRawMutex, HidInstruction, HID_QUEUE_SIZE> = Channel::new();
     let tx_ch = CHANNEL.sender();
     let rx_ch = CHANNEL.receiver();
 
+    static BATTERY_SIGNAL: Signal<CriticalSectionRawMutex, BatteryStatus> = Signal::new();
+
     // Launch hid controller with channel receiver
-    unwrap!(spawner.spawn(hid_usb_controller(hid_mouse, hid_keyboard, hid_media, rx_ch)));
+    unwrap!(spawner.spawn(hid_usb_controller(hid_reports, rx_ch)));
+    unwrap!(spawner.spawn(hid_battery_reporter(hid_battery, &BATTERY_SIGNAL)));
+    unwrap!(spawner.spawn(keyboard_output_task(keyboard_output)));
+    unwrap!(spawner.spawn(keyboard_leds_task(&RELAY)));
+    unwrap!(spawner.spawn(midi_task(midi, settings)));
     // Launch TCP task with channel sender
-    unwrap!(spawner.spawn(tcp_server_task(control, stack, tx_ch)));
+    unwrap!(spawner.spawn(tcp_server_task(control, stack, tx_ch, &BATTERY_SIGNAL, settings, &STATUS, &RELAY)));
 }
\ No newline at end of file
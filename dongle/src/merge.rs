@@ -0,0 +1,140 @@
+//! Merges the reports of every connected glove into the single set of HID reports
+//! the host sees. The policy is fixed so the result never depends on arrival order:
+//! - Pointer movement, wheel and pan come only from the pointer glove, the first
+//!   connected glove on the `pointer_hand`, or the first connected glove if none is.
+//! - Buttons and keyboard modifiers held by any glove are combined, so one hand
+//!   can click while the other holds a modifier chord.
+//! - Held keys are listed pointer glove first, then by slot, until the report is full;
+//!   the rest are kept as extra keys for an NKRO keyboard.
+//! - Consumer and system keys are held like keys, one of each: the pointer glove's,
+//!   else the one in the lowest slot.
+//! - The gamepad axes follow the same order among gloves in gamepad mode, its
+//!   buttons are combined.
+
+use usbd_hid::descriptor::{MouseReport, KeyboardReport, MediaKeyboardReport};
+
+use shared::{
+    custom_hid::{HidInstruction, KeyBitmap, GamepadReport},
+    config::Hand,
+};
+
+use crate::status::Gloves;
+
+const KEYCODES: usize = 6;
+
+/// Input a glove keeps pressed until its next report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldInput {
+    pub buttons: u8,
+    pub modifier: u8,
+    pub keycodes: [u8; KEYCODES],
+    pub extra_keys: KeyBitmap,
+    pub consumer: u16,
+    pub system: u8,
+}
+
+impl Default for HeldInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeldInput {
+    pub const fn new() -> Self {
+        HeldInput {
+            buttons: 0,
+            modifier: 0,
+            keycodes: [0; KEYCODES],
+            extra_keys: KeyBitmap::new(),
+            consumer: 0,
+            system: 0,
+        }
+    }
+
+    pub fn from_instruction(instruction: &HidInstruction) -> Self {
+        HeldInput {
+            buttons: instruction.mouse.buttons,
+            modifier: instruction.keyboard.modifier,
+            keycodes: instruction.keyboard.keycodes,
+            extra_keys: instruction.extra_keys,
+            consumer: instruction.media.usage_id,
+            system: instruction.system,
+        }
+    }
+}
+
+/// Slot of the glove that moves the pointer
+fn pointer_slot(gloves: &Gloves, pointer_hand: Hand) -> Option<usize> {
+    gloves.iter().position(|glove| glove.is_connected() && glove.hand == Some(pointer_hand))
+        .or_else(|| gloves.iter().position(|glove| glove.is_connected()))
+}
+
+/// Combined input held by every connected glove, with no movement
+pub fn held_report(gloves: &Gloves, pointer_hand: Hand) -> HidInstruction {
+    let mut mouse = MouseReport { buttons: 0, x: 0, y: 0, wheel: 0, pan: 0 };
+    let mut keyboard = KeyboardReport { modifier: 0, reserved: 0, leds: 0, keycodes: [0; KEYCODES] };
+    let mut media = MediaKeyboardReport { usage_id: 0 };
+    let mut system = 0;
+    let mut extra_keys = KeyBitmap::new();
+
+    let pointer = pointer_slot(gloves, pointer_hand);
+    let others = (0..gloves.len()).filter(|slot| Some(*slot) != pointer);
+    let mut free = keyboard.keycodes.iter_mut();
+    for slot in pointer.into_iter().chain(others) {
+        let glove = &gloves[slot];
+        if !glove.is_connected() {
+            continue;
+        }
+        mouse.buttons |= glove.held.buttons;
+        keyboard.modifier |= glove.held.modifier;
+        for &code in glove.held.keycodes.iter().filter(|code| **code != 0) {
+            match free.next() {
+                Some(entry) => *entry = code,
+                None => extra_keys.press(code),
+            }
+        }
+        extra_keys.merge(&glove.held.extra_keys);
+        if media.usage_id == 0 {
+            media.usage_id = glove.held.consumer;
+        }
+        if system == 0 {
+            system = glove.held.system;
+        }
+    }
+    HidInstruction { mouse, keyboard, media, system, extra_keys }
+}
+
+/// Records a report from the glove in `slot` and gives the merged report to send
+pub fn merge(gloves: &mut Gloves, slot: usize, pointer_hand: Hand, instruction: &HidInstruction) -> HidInstruction {
+    gloves[slot].held = HeldInput::from_instruction(instruction);
+    // A glove sending HID reports left gamepad mode
+    gloves[slot].gamepad = None;
+    let mut merged = held_report(gloves, pointer_hand);
+    if pointer_slot(gloves, pointer_hand) == Some(slot) {
+        merged.mouse.x = instruction.mouse.x;
+        merged.mouse.y = instruction.mouse.y;
+        merged.mouse.wheel = instruction.mouse.wheel;
+        merged.mouse.pan = instruction.mouse.pan;
+    }
+    merged
+}
+
+/// Combined gamepad of the connected gloves in gamepad mode, centered if none is
+pub fn gamepad_report(gloves: &Gloves, pointer_hand: Hand) -> GamepadReport {
+    let pointer = pointer_slot(gloves, pointer_hand);
+    let others = (0..gloves.len()).filter(|slot| Some(*slot) != pointer);
+    let mut reports = pointer.into_iter().chain(others)
+        .filter(|slot| gloves[*slot].is_connected())
+        .filter_map(|slot| gloves[slot].gamepad);
+    let Some(mut merged) = reports.next() else { return GamepadReport::new() };
+    for report in reports {
+        merged.buttons |= report.buttons;
+    }
+    merged
+}
+
+/// Records a gamepad report from the glove in `slot` and gives the merged one to send
+pub fn merge_gamepad(gloves: &mut Gloves, slot: usize, pointer_hand: Hand, report: &GamepadReport) -> GamepadReport {
+    gloves[slot].gamepad = Some(*report);
+    gamepad_report(gloves, pointer_hand)
+}
@@ -0,0 +1,178 @@
+//! MIDI mapping of the gamepad stream: the bend of each finger is a control change,
+//! the tap holds a note and the wrist roll bends the pitch. The USB MIDI interface
+//! is only added with the `midi` feature, otherwise the stream is ignored.
+//! Only gloves in gamepad output mode send that stream, in pointer mode MIDI stays silent.
+
+use embassy_sync::{
+    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use shared::{
+    custom_hid::GamepadReport,
+    config::SharedSettings,
+};
+
+#[cfg(feature = "midi")]
+pub use enabled::*;
+#[cfg(not(feature = "midi"))]
+pub use disabled::*;
+
+/// Latest merged gamepad state, only the newest one matters
+static GAMEPAD: Signal<CriticalSectionRawMutex, GamepadReport> = Signal::new();
+
+/// Hands the gamepad state to the MIDI mapping
+pub fn play(report: GamepadReport) {
+    GAMEPAD.signal(report);
+}
+
+/// USB MIDI interface fed from the gamepad stream
+#[cfg(feature = "midi")]
+mod enabled {
+    use embassy_rp::{
+        peripherals::USB,
+        usb::Driver,
+    };
+    use embassy_usb::{
+        Builder,
+        class::midi::MidiClass,
+        driver::EndpointError,
+    };
+    use heapless::Vec;
+    use shared::{
+        custom_hid::{GAMEPAD_FINGERS, GAMEPAD_TAP_BUTTON},
+        config::MidiSettings,
+    };
+    use super::*;
+
+    const MIDI_PACKET_SIZE: u16 = 64;
+
+    // USB MIDI event packets: [cable and code index][status][data][data]
+    const CIN_NOTE_OFF: u8      = 0x08;
+    const CIN_NOTE_ON: u8       = 0x09;
+    const CIN_CONTROL: u8       = 0x0B;
+    const CIN_PITCH_BEND: u8    = 0x0E;
+    const EVENT_SIZE: usize     = 4;
+
+    /// Velocity of the tap note
+    const NOTE_VELOCITY: u8     = 100;
+    /// Roll in degrees that bends the pitch all the way
+    const BEND_RANGE: f32       = 90.0;
+    const BEND_CENTER: u16      = 0x2000;
+    const BEND_MAX: u16         = 0x3FFF;
+
+    /// A control change per finger, a note off and on, and the pitch bend
+    const MAX_EVENTS: usize = GAMEPAD_FINGERS + 3;
+    type Events = Vec<u8, { MAX_EVENTS * EVENT_SIZE }>;
+
+    /// Values last sent to the host, None until sent
+    struct Mapping {
+        controls: [Option<u8>; GAMEPAD_FINGERS],
+        /// Event that releases the held note
+        note_off: Option<[u8; EVENT_SIZE]>,
+        bend: Option<u16>,
+    }
+
+    impl Mapping {
+        fn new() -> Self {
+            Mapping { controls: [None; GAMEPAD_FINGERS], note_off: None, bend: None }
+        }
+
+        /// Events for whatever changed since the last report
+        fn events(&mut self, report: &GamepadReport, midi: &MidiSettings) -> Events {
+            let channel = midi.channel.saturating_sub(1) & 0x0F;
+            let mut events = Events::new();
+            // MAX_EVENTS covers every event a report can produce
+            let mut push = |event: [u8; EVENT_SIZE]| events.extend_from_slice(&event).unwrap();
+
+            for (finger, (control, bend)) in self.controls.iter_mut().zip(report.fingers).enumerate() {
+                let value = bend >> 1;
+                if *control != Some(value) {
+                    push([CIN_CONTROL, 0xB0 | channel, midi.cc + finger as u8, value]);
+                    *control = Some(value);
+                }
+            }
+
+            let tap = report.buttons & GAMEPAD_TAP_BUTTON != 0;
+            match (tap, self.note_off) {
+                (true, None) => {
+                    push([CIN_NOTE_ON, 0x90 | channel, midi.note, NOTE_VELOCITY]);
+                    self.note_off = Some([CIN_NOTE_OFF, 0x80 | channel, midi.note, 0]);
+                }
+                (false, Some(note_off)) => {
+                    push(note_off);
+                    self.note_off = None;
+                }
+                _ => {}
+            }
+
+            let roll = report.orientation[0] as f32 * 180.0 / i16::MAX as f32;
+            let bend = (BEND_CENTER as f32 * (1.0 + roll / BEND_RANGE)).clamp(0.0, BEND_MAX as f32) as u16;
+            if self.bend != Some(bend) {
+                push([CIN_PITCH_BEND, 0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8]);
+                self.bend = Some(bend);
+            }
+            events
+        }
+    }
+
+    pub struct MidiOutput {
+        class: MidiClass<'static, Driver<'static, USB>>,
+    }
+
+    impl MidiOutput {
+        /// Adds a MIDI streaming interface with one jack each way
+        pub fn new(builder: &mut Builder<'static, Driver<'static, USB>>) -> Self {
+            MidiOutput { class: MidiClass::new(builder, 1, 1, MIDI_PACKET_SIZE) }
+        }
+
+        pub async fn run(mut self, settings: &'static SharedSettings) -> ! {
+            loop {
+                self.class.wait_connection().await;
+                log::info!("MIDI host connected");
+                // The host state is unknown, every value is sent again
+                let mut mapping = Mapping::new();
+                loop {
+                    let report = GAMEPAD.wait().await;
+                    let midi = settings.lock(|settings| settings.borrow().midi);
+                    let events = mapping.events(&report, &midi);
+                    if events.is_empty() {
+                        continue;
+                    }
+                    match self.class.write_packet(&events).await {
+                        Ok(()) => {}
+                        Err(EndpointError::Disabled) => break,
+                        Err(e) => log::warn!("Failed to send MIDI events: {:?}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+/// No MIDI interface, the stream is dropped
+#[cfg(not(feature = "midi"))]
+mod disabled {
+    use embassy_rp::{
+        peripherals::USB,
+        usb::Driver,
+    };
+    use embassy_usb::Builder;
+    use super::*;
+
+    pub struct MidiOutput;
+
+    impl MidiOutput {
+        pub fn new(_builder: &mut Builder<'static, Driver<'static, USB>>) -> Self {
+            MidiOutput
+        }
+
+        pub async fn run(self, _settings: &'static SharedSettings) {}
+    }
+}
+
+
+#[embassy_executor::task]
+pub async fn midi_task(midi: MidiOutput, settings: &'static SharedSettings) {
+    midi.run(settings).await
+}
@@ -0,0 +1,77 @@
+//! Which gloves may use the dongle. Until a glove is paired any glove is accepted;
+//! afterwards only the paired ones, or one new glove connecting within
+//! `PAIRING_WINDOW` of the `pair` command, which then takes a paired entry.
+
+use embassy_sync::{
+    channel::Channel,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::Instant;
+use portable_atomic::{AtomicU64, Ordering};
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::{
+    definitions::{PAIRING_WINDOW, MAX_GLOVES},
+    config::{SharedSettings, ConfigKey, ConfigValue},
+    storage::SharedStore,
+};
+
+use crate::status::SharedStatus;
+
+/// Setting holding each paired entry
+const PAIRED_KEYS: [ConfigKey; MAX_GLOVES] = [ConfigKey::PairedGlove1, ConfigKey::PairedGlove2];
+
+/// Uptime in ms until which a new glove may pair
+static WINDOW_END_MS: AtomicU64 = AtomicU64::new(0);
+/// Device ids accepted during the window, waiting to be persisted
+static NEW_GLOVES: Channel<CriticalSectionRawMutex, u32, MAX_GLOVES> = Channel::new();
+
+/// Lets the next glove that isn't paired connect and pair
+pub fn open_window() {
+    WINDOW_END_MS.store((Instant::now() + PAIRING_WINDOW).as_millis(), Ordering::Relaxed);
+}
+
+/// Whether a glove may stay connected, pairing it if the window is open
+pub fn admit(device_id: u32, settings: &'static SharedSettings) -> bool {
+    let paired = settings.lock(|settings| settings.borrow().link.paired);
+    if paired.contains(&device_id) {
+        return true;
+    }
+    // One glove per pair command
+    let window_end = WINDOW_END_MS.swap(0, Ordering::Relaxed);
+    if Instant::now().as_millis() < window_end {
+        log::info!("Pairing glove {:08x}", device_id);
+        let _ = NEW_GLOVES.try_send(device_id);
+        return true;
+    }
+    WINDOW_END_MS.store(window_end, Ordering::Relaxed);
+    paired.iter().all(|id| *id == 0)
+}
+
+
+/// Persists newly paired gloves, in a free entry or one of a glove not connected
+#[embassy_executor::task]
+pub async fn pairing_task(settings: &'static SharedSettings, store: &'static SharedStore, status: &'static SharedStatus) -> ! {
+    loop {
+        let device_id = NEW_GLOVES.receive().await;
+        let mut store = store.lock().await;
+        let connected = status.lock(|status| status.borrow().map(|glove| glove.device_id));
+        let updated = settings.lock(|settings| {
+            let mut updated = settings.borrow().clone();
+            let paired = updated.link.paired;
+            let entry = paired.iter().position(|id| *id == 0)
+                .or_else(|| paired.iter().position(|id| !connected.contains(&Some(*id))))
+                .unwrap_or(0);
+            let key = PAIRED_KEYS[entry];
+            updated.set(key, ConfigValue::Int(device_id)).map(|()| (key, updated))
+        });
+        let Ok((key, updated)) = updated else { continue };
+        match store.save(&updated, key) {
+            Ok(()) => {
+                settings.lock(|settings| *settings.borrow_mut() = updated);
+                log::info!("Glove {:08x} paired as {}", device_id, key.name());
+            }
+            Err(e) => log::warn!("Error {:?} while saving the paired glove", e),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use core::fmt;
+
+use embassy_sync::{
+    channel::Channel,
+    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::{Duration, with_timeout};
+use heapless::String;
+use portable_atomic::{AtomicU8, Ordering};
+
+use shared::{
+    definitions::{RELAY_TIMEOUT, CALIBRATION_TIME, MAX_GLOVES},
+    config::{ConfigKey, ConfigValue, Hand, PROFILE_NAME_LEN},
+    protocol::{DongleMessage, ConfigReply, ConfigRejection, ProfileAction, HapticPattern},
+};
+
+use crate::status::{SharedStatus, glove_slot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayError {
+    /// No glove connected on the requested hand
+    NoGlove,
+    /// The glove didn't answer in time, it may be disconnected or asleep
+    Timeout,
+    Rejected(ConfigRejection),
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayError::NoGlove => write!(f, "no such glove connected"),
+            RelayError::Timeout => write!(f, "the glove didn't answer"),
+            RelayError::Rejected(ConfigRejection::UnknownKey) => write!(f, "the glove doesn't know this key"),
+            RelayError::Rejected(ConfigRejection::Storage) => write!(f, "applied but not saved on the glove"),
+            RelayError::Rejected(rejection) => write!(f, "{}", rejection),
+        }
+    }
+}
+
+/// Forwards requests to a glove over its TCP link and hands back its replies
+pub struct GloveRelay {
+    /// Requests for the connection in each glove slot
+    requests: [Channel<CriticalSectionRawMutex, DongleMessage, 1>; MAX_GLOVES],
+    replies: Signal<CriticalSectionRawMutex, ConfigReply>,
+    seq: AtomicU8,
+    status: &'static SharedStatus,
+}
+
+impl GloveRelay {
+    pub const fn new(status: &'static SharedStatus) -> Self {
+        GloveRelay {
+            requests: [const { Channel::new() }; MAX_GLOVES],
+            replies: Signal::new(),
+            seq: AtomicU8::new(0),
+            status,
+        }
+    }
+
+    /// Next request for the connection in `slot` to send
+    pub async fn next_request(&self, slot: usize) -> DongleMessage {
+        self.requests[slot].receive().await
+    }
+
+    /// Drops requests queued while no glove was connected in `slot`
+    pub fn clear_requests(&self, slot: usize) {
+        self.requests[slot].clear();
+    }
+
+    fn slot(&self, hand: Option<Hand>) -> Result<usize, RelayError> {
+        self.status.lock(|status| glove_slot(&status.borrow(), hand)).ok_or(RelayError::NoGlove)
+    }
+
+    /// Hands a reply received by the TCP server to the waiting request
+    pub fn reply(&self, reply: ConfigReply) {
+        self.replies.signal(reply);
+    }
+
+    async fn request(&self, slot: usize, build: impl FnOnce(u8) -> DongleMessage) -> Result<ConfigValue, RelayError> {
+        self.request_within(slot, RELAY_TIMEOUT, build).await
+    }
+
+    async fn request_within(&self, slot: usize, timeout: Duration, build: impl FnOnce(u8) -> DongleMessage) -> Result<ConfigValue, RelayError> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        self.replies.reset();
+        let exchange = async {
+            self.requests[slot].send(build(seq)).await;
+            loop {
+                // Late replies to timed out requests are skipped
+                let reply = self.replies.wait().await;
+                if reply.seq == seq {
+                    return reply.result.map_err(RelayError::Rejected);
+                }
+            }
+        };
+        with_timeout(timeout, exchange).await.map_err(|_| RelayError::Timeout)?
+    }
+
+    /// Reads a setting of the glove on `hand`, or of the first connected glove
+    pub async fn get(&self, hand: Option<Hand>, key: ConfigKey) -> Result<ConfigValue, RelayError> {
+        let slot = self.slot(hand)?;
+        self.request(slot, |seq| DongleMessage::ConfigGet { seq, key: key.id() }).await
+    }
+
+    /// Changes a glove setting, returning the value it now holds
+    pub async fn set(&self, hand: Option<Hand>, key: ConfigKey, value: ConfigValue) -> Result<ConfigValue, RelayError> {
+        let slot = self.slot(hand)?;
+        let value = self.request(slot, |seq| DongleMessage::ConfigSet { seq, key: key.id(), value }).await?;
+        // The glove only announces its hand when connecting
+        if let (ConfigKey::Hand, ConfigValue::Hand(hand)) = (key, &value) {
+            self.status.lock(|status| status.borrow_mut()[slot].hand = Some(*hand));
+        }
+        Ok(value)
+    }
+
+    /// Runs a profile command on a glove, returning the active profile name,
+    /// or every profile name when listing
+    pub async fn profile(&self, hand: Option<Hand>, action: ProfileAction, name: String<PROFILE_NAME_LEN>) -> Result<ConfigValue, RelayError> {
+        let slot = self.slot(hand)?;
+        self.request(slot, |seq| DongleMessage::Profile { seq, action, name }).await
+    }
+
+    /// Measures the flex bands of a glove, returning them once the hand was opened and closed
+    pub async fn calibrate(&self, hand: Option<Hand>) -> Result<ConfigValue, RelayError> {
+        let slot = self.slot(hand)?;
+        self.request_within(slot, CALIBRATION_TIME + RELAY_TIMEOUT, |seq| DongleMessage::Calibrate { seq }).await
+    }
+
+    /// Queues a message the glove doesn't answer
+    async fn notify(&self, hand: Option<Hand>, message: DongleMessage) -> Result<(), RelayError> {
+        let slot = self.slot(hand)?;
+        with_timeout(RELAY_TIMEOUT, self.requests[slot].send(message)).await
+            .map_err(|_| RelayError::Timeout)
+    }
+
+    /// Asks a glove to restart
+    pub async fn reboot(&self, hand: Option<Hand>) -> Result<(), RelayError> {
+        self.notify(hand, DongleMessage::Reboot).await
+    }
+
+    /// Vibrates a glove
+    pub async fn haptic(&self, hand: Option<Hand>, pattern: HapticPattern) -> Result<(), RelayError> {
+        self.notify(hand, DongleMessage::Haptic(pattern)).await
+    }
+
+    /// Shows the host keyboard LEDs on every connected glove
+    pub async fn keyboard_leds(&self, leds: u8) {
+        let connected = self.status.lock(|status| status.borrow().map(|glove| glove.is_connected()));
+        for slot in (0..MAX_GLOVES).filter(|slot| connected[*slot]) {
+            let sent = with_timeout(RELAY_TIMEOUT, self.requests[slot].send(DongleMessage::KeyboardLeds(leds))).await;
+            if sent.is_err() {
+                log::warn!("Glove {} missed the keyboard LEDs", slot);
+            }
+        }
+    }
+}
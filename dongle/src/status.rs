@@ -0,0 +1,121 @@
+use core::cell::RefCell;
+
+use embassy_net::IpEndpoint;
+use embassy_time::{Duration, Instant};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+use shared::{
+    definitions::{MAX_GLOVES, LINK_STATS_WINDOW},
+    protocol::{BatteryStatus, PowerState},
+    config::Hand,
+    custom_hid::GamepadReport,
+};
+
+use crate::merge::HeldInput;
+
+/// Heartbeat figures of a glove link
+#[derive(Debug, Clone, Copy)]
+pub struct LinkStats {
+    /// Ping waiting for its pong and when it was sent
+    pending: Option<(u16, Instant)>,
+    sent: u32,
+    lost: u32,
+    /// Round trip time of the last answered ping
+    pub rtt: Option<Duration>,
+    /// Share of unanswered pings over the last full window
+    pub loss_percent: Option<u8>,
+    /// Signal strength received by the glove, in dBm
+    pub rssi: Option<i8>,
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkStats {
+    pub const fn new() -> Self {
+        LinkStats { pending: None, sent: 0, lost: 0, rtt: None, loss_percent: None, rssi: None }
+    }
+
+    /// A ping still unanswered when the next one goes out counts as lost
+    pub fn ping_sent(&mut self, seq: u16, now: Instant) {
+        if self.pending.is_some() {
+            self.lost += 1;
+        }
+        self.pending = Some((seq, now));
+        self.sent += 1;
+        if self.sent == LINK_STATS_WINDOW {
+            self.loss_percent = Some((self.lost * 100 / LINK_STATS_WINDOW) as u8);
+            self.sent = 0;
+            self.lost = 0;
+        }
+    }
+
+    pub fn pong_received(&mut self, seq: u16, rssi: i8, now: Instant) {
+        self.rssi = Some(rssi);
+        // Pongs to pings already counted as lost are ignored
+        if let Some((_, sent_at)) = self.pending.filter(|(pending, _)| *pending == seq) {
+            self.rtt = Some(now - sent_at);
+            self.pending = None;
+        }
+    }
+}
+
+/// Last known state of a glove, as seen from the link
+#[derive(Debug, Clone, Copy)]
+pub struct GloveStatus {
+    /// Remote endpoint while a glove is connected
+    pub peer: Option<IpEndpoint>,
+    /// Identity announced by the glove once connected
+    pub device_id: Option<u32>,
+    pub hand: Option<Hand>,
+    pub battery: Option<BatteryStatus>,
+    pub power: PowerState,
+    /// Input from its last report, merged with the other gloves
+    pub held: HeldInput,
+    /// Last gamepad report, while the glove is in gamepad mode
+    pub gamepad: Option<GamepadReport>,
+    pub link: LinkStats,
+}
+
+impl Default for GloveStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GloveStatus {
+    pub const fn new() -> Self {
+        GloveStatus {
+            peer: None,
+            device_id: None,
+            hand: None,
+            battery: None,
+            power: PowerState::Active,
+            held: HeldInput::new(),
+            gamepad: None,
+            link: LinkStats::new(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.peer.is_some()
+    }
+}
+
+/// Status of every glove slot, one per concurrent connection
+pub type Gloves = [GloveStatus; MAX_GLOVES];
+
+/// Glove status updated by the TCP server and read by the CLI
+pub type SharedStatus = Mutex<CriticalSectionRawMutex, RefCell<Gloves>>;
+
+pub const fn new_status() -> SharedStatus {
+    Mutex::new(RefCell::new([GloveStatus::new(); MAX_GLOVES]))
+}
+
+/// Slot of the first connected glove on `hand`, on any hand when None
+pub fn glove_slot(gloves: &Gloves, hand: Option<Hand>) -> Option<usize> {
+    gloves.iter().position(|glove| glove.is_connected() && (hand.is_none() || glove.hand == hand))
+}
@@ -1,7 +1,11 @@
 use {defmt_rtt as _, panic_probe as _};
 
-use core::str::FromStr;
+use core::net::Ipv4Addr;
 use embassy_rp::clocks::RoscRng;
+use embassy_futures::{
+    join::{join, join_array},
+    select::{select3, Either3},
+};
 use embassy_net::{
     Config,
     Stack,
@@ -10,46 +14,277 @@ use embassy_net::{
 };
 use embassy_sync::{
     channel::Sender,
+    signal::Signal,
     blocking_mutex::raw::CriticalSectionRawMutex,
 };
+use embassy_time::{Instant, Timer};
+use embedded_io_async::Write;
 use cyw43::JoinOptions;
 use static_cell::StaticCell;
 
 use shared::{
     definitions::{
-        WIFI_NETWORK, WIFI_PASSWORD,
-        DONGLE_IP,
         // TCP_CHANNEL,
         TCP_ENDPOINT,
         SOCKET_TIMEOUT,
-        CHANNEL_SIZE
+        HID_QUEUE_SIZE,
+        MAX_GLOVES,
+        HEARTBEAT_PERIOD,
+        LINK_TIMEOUT,
+        SLEEP_LINK_TIMEOUT,
     },
-    custom_hid::HidInstruction
+    custom_hid::HidInstruction,
+    protocol::{GloveMessage, DongleMessage, BatteryStatus, PowerState, FrameDecoder, LinkMessage},
+    config::{SharedSettings, Hand},
+};
+
+use crate::{
+    status::{SharedStatus, GloveStatus},
+    relay::GloveRelay,
+    merge::{HeldInput, merge, held_report, merge_gamepad, gamepad_report},
+    hid::send_gamepad,
+    hid_reports::current_keyboard_leds,
+    telemetry,
+    pairing,
 };
 
 
-pub fn network_config(net_device: cyw43::NetDriver<'static>) -> (embassy_net::Stack<'static>, embassy_net::Runner<'static, cyw43::NetDriver<'static>>) {
-    // Configure the network
-    let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: embassy_net::Ipv4Cidr::new(embassy_net::Ipv4Address::from_str(DONGLE_IP).unwrap(), 16),
-        dns_servers: heapless::Vec::new(),
-        gateway: None,
-    });
+pub fn network_config(net_device: cyw43::NetDriver<'static>, dongle_ip: Ipv4Addr) -> (embassy_net::Stack<'static>, embassy_net::Runner<'static, cyw43::NetDriver<'static>>) {
+    // Configure the network, an unspecified address is taken from DHCP
+    let config = if dongle_ip.is_unspecified() {
+        Config::dhcpv4(Default::default())
+    } else {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: embassy_net::Ipv4Cidr::new(dongle_ip, 16),
+            dns_servers: heapless::Vec::new(),
+            gateway: None,
+        })
+    };
 
     // Generate random seed
     let seed = RoscRng.next_u64();
 
-    // Init network stack
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    // Init network stack, sockets for the TCP link of each glove, UDP discovery, DHCP and DNS
+    static RESOURCES: StaticCell<StackResources<{ MAX_GLOVES + 3 }>> = StaticCell::new();
     let (stack, runner) = embassy_net::new(net_device, config, RESOURCES.init(StackResources::new()), seed);
 
     (stack, runner)
 }
 
 
+/// Everything the connections of the glove slots share
+struct Server {
+    stack: Stack<'static>,
+    tx_ch: Sender<'static, CriticalSectionRawMutex, HidInstruction, HID_QUEUE_SIZE>,
+    battery_signal: &'static Signal<CriticalSectionRawMutex, BatteryStatus>,
+    settings: &'static SharedSettings,
+    status: &'static SharedStatus,
+    relay: &'static GloveRelay,
+    /// Raised whenever a glove connects or disconnects
+    connections: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Server {
+    fn pointer_hand(&self) -> Hand {
+        self.settings.lock(|settings| settings.borrow().link.pointer_hand)
+    }
+
+    /// Reports the emptiest battery among the connected gloves to the host
+    fn report_battery(&self) {
+        let lowest = self.status.lock(|status| {
+            status.borrow().iter()
+                .filter(|glove| glove.is_connected())
+                .filter_map(|glove| glove.battery)
+                .min_by_key(|battery| battery.percent)
+        });
+        if let Some(battery) = lowest {
+            self.battery_signal.signal(battery);
+        }
+    }
+
+    /// Resets the input held by `slot` and sends what the other gloves still hold,
+    /// so nothing stays pressed on the host past a link change
+    async fn release(&self, slot: usize, glove: GloveStatus) {
+        let pointer_hand = self.pointer_hand();
+        let (released, gamepad) = self.status.lock(|status| {
+            let mut status = status.borrow_mut();
+            status[slot] = glove;
+            (held_report(&status, pointer_hand), gamepad_report(&status, pointer_hand))
+        });
+        send_gamepad(gamepad);
+        self.tx_ch.send(released).await;
+    }
+
+    /// Updates the status of `slot`, giving the merged report to send for HID messages
+    /// and for a glove falling asleep
+    fn handle_message(&self, slot: usize, message: GloveMessage) -> Option<HidInstruction> {
+        match message {
+            GloveMessage::Hid(hid_instruction) => {
+                let pointer_hand = self.pointer_hand();
+                return Some(self.status.lock(|status| merge(&mut status.borrow_mut(), slot, pointer_hand, &hid_instruction)));
+            }
+            GloveMessage::Gamepad(report) => {
+                let pointer_hand = self.pointer_hand();
+                send_gamepad(self.status.lock(|status| merge_gamepad(&mut status.borrow_mut(), slot, pointer_hand, &report)));
+            }
+            GloveMessage::Telemetry(sample) => telemetry::relay(slot, sample),
+            GloveMessage::Battery(battery) => {
+                log::info!("Glove {} battery: {} mV, {}%", slot, battery.millivolts, battery.percent);
+                self.status.lock(|status| status.borrow_mut()[slot].battery = Some(battery));
+                self.report_battery();
+            }
+            GloveMessage::Power(state) => {
+                log::info!("Glove {} power state: {:?}", slot, state);
+                let pointer_hand = self.pointer_hand();
+                let released = self.status.lock(|status| {
+                    let mut status = status.borrow_mut();
+                    status[slot].power = state;
+                    // A sleeping glove stops reporting, nothing it held may stay pressed
+                    (state == PowerState::Sleep).then(|| {
+                        status[slot].held = HeldInput::new();
+                        status[slot].gamepad = None;
+                        (held_report(&status, pointer_hand), gamepad_report(&status, pointer_hand))
+                    })
+                });
+                if let Some((released, gamepad)) = released {
+                    send_gamepad(gamepad);
+                    return Some(released);
+                }
+            }
+            GloveMessage::ConfigReply(reply) => self.relay.reply(reply),
+            GloveMessage::Pong { seq, rssi } => {
+                self.status.lock(|status| status.borrow_mut()[slot].link.pong_received(seq, rssi, Instant::now()));
+            }
+            GloveMessage::Hello(hello) => {
+                log::info!("Glove {} is {:08x}, {} hand", slot, hello.device_id, hello.hand);
+                self.status.lock(|status| {
+                    let glove = &mut status.borrow_mut()[slot];
+                    glove.device_id = Some(hello.device_id);
+                    glove.hand = Some(hello.hand);
+                });
+            }
+        }
+        None
+    }
+
+    /// Accepts gloves into `slot`, one connection at a time
+    async fn serve(&self, slot: usize) -> ! {
+        let mut rx_buffer = [0; 4096];
+        let mut tx_buffer = [0; 4096];
+        let mut decoder = FrameDecoder::new();
+
+        loop {
+            let mut socket = TcpSocket::new(self.stack, &mut rx_buffer, &mut tx_buffer);
+            socket.set_timeout(Some(SOCKET_TIMEOUT));
+
+            log::info!("Glove {} listening on TCP: {TCP_ENDPOINT}...", slot);
+            if let Err(e) = socket.accept(TCP_ENDPOINT).await {
+                log::warn!("accept error: {:?}", e);
+                continue;
+            }
+
+            log::info!("Glove {} connected from {:?}", slot, socket.remote_endpoint());
+            decoder.clear();
+            self.release(slot, GloveStatus { peer: socket.remote_endpoint(), ..GloveStatus::new() }).await;
+            self.relay.clear_requests(slot);
+            self.connections.signal(());
+
+            // Heartbeats catch a vanished glove long before the socket timeout
+            let mut next_ping = Instant::now();
+            let mut last_rx = Instant::now();
+            let mut ping_seq: u16 = 0;
+
+            'link: loop {
+                // Checked on every pass, a glove streaming back to back always has data to read
+                if Instant::now() >= next_ping {
+                    let timeout = match self.status.lock(|status| status.borrow()[slot].power) {
+                        PowerState::Active => LINK_TIMEOUT,
+                        PowerState::Sleep => SLEEP_LINK_TIMEOUT,
+                    };
+                    if last_rx.elapsed() > timeout {
+                        log::warn!("Glove {} silent for {} ms, dropping it", slot, last_rx.elapsed().as_millis());
+                        break;
+                    }
+                    ping_seq = ping_seq.wrapping_add(1);
+                    if let Err(e) = socket.write_all(&DongleMessage::Ping { seq: ping_seq }.to_frame()).await {
+                        log::warn!("write error: {:?}", e);
+                        break;
+                    }
+                    self.status.lock(|status| status.borrow_mut()[slot].link.ping_sent(ping_seq, Instant::now()));
+                    next_ping = Instant::now() + HEARTBEAT_PERIOD;
+                }
+
+                // Receives data from TCP Client, forwards a request to it or waits for the next ping
+                match select3(socket.read(decoder.unfilled()), self.relay.next_request(slot), Timer::at(next_ping)).await {
+                    Either3::Third(()) => {}
+                    Either3::Second(request) => {
+                        if let Err(e) = socket.write_all(&request.to_frame()).await {
+                            log::warn!("write error: {:?}", e);
+                            break;
+                        }
+                    }
+                    Either3::First(Err(e)) => {
+                        log::warn!("read error: {:?}", e);
+                        break;
+                    }
+                    Either3::First(Ok(0)) => {
+                        log::warn!("read EOF");
+                        break;
+                    }
+                    Either3::First(Ok(idx)) => {
+                        last_rx = Instant::now();
+                        decoder.filled(idx);
+                        while let Some(message) = decoder.next_message::<GloveMessage>() {
+                            match message {
+                                Err(e) => log::warn!("Dropped frame: {:?}", e),
+                                Ok(GloveMessage::Hello(hello)) if !pairing::admit(hello.device_id, self.settings) => {
+                                    log::warn!("Glove {:08x} is not paired, dropping it", hello.device_id);
+                                    break 'link;
+                                }
+                                Ok(message) => {
+                                    let hello = matches!(message, GloveMessage::Hello(_));
+                                    if let Some(report) = self.handle_message(slot, message) {
+                                        self.tx_ch.send(report).await;
+                                    }
+                                    // The host only reports LED changes, a new glove starts from the current ones
+                                    if hello {
+                                        let leds = DongleMessage::KeyboardLeds(current_keyboard_leds());
+                                        if let Err(e) = socket.write_all(&leds.to_frame()).await {
+                                            log::warn!("write error: {:?}", e);
+                                            break 'link;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                };
+            }
+
+            self.release(slot, GloveStatus::new()).await;
+            self.report_battery();
+            self.connections.signal(());
+        }
+    }
+
+    /// Keeps the LED on while any glove is connected
+    async fn show_connections(&self, control: &mut cyw43::Control<'static>) -> ! {
+        loop {
+            self.connections.wait().await;
+            let connected = self.status.lock(|status| status.borrow().iter().any(GloveStatus::is_connected));
+            control.gpio_set(0, connected).await;
+        }
+    }
+}
+
+
 #[embassy_executor::task]
 pub async fn tcp_server_task(
-    mut control: cyw43::Control<'static>, stack: Stack<'static>, tx_ch: Sender<'static, CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE>
+    mut control: cyw43::Control<'static>, stack: Stack<'static>, tx_ch: Sender<'static, CriticalSectionRawMutex, HidInstruction, HID_QUEUE_SIZE>,
+    battery_signal: &'static Signal<CriticalSectionRawMutex, BatteryStatus>,
+    settings: &'static SharedSettings,
+    status: &'static SharedStatus,
+    relay: &'static GloveRelay
 ) -> ! {
     /* Create access point instead of connecting to WIFI in this way:
     //control.start_ap_open("cyw43", 5).await;
@@ -64,8 +299,9 @@ pub async fn tcp_server_task(
     */
 
     // Try connection wifi
+    let network = settings.lock(|settings| settings.borrow().network.clone());
     while let Err(err) = control
-        .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
+        .join(&network.wifi_network, JoinOptions::new(network.wifi_password.as_bytes()))
         .await
     {
         log::info!("join failed with status={}", err.status);
@@ -80,45 +316,17 @@ pub async fn tcp_server_task(
     // And now we can use it!
     log::info!("Stack is up!");
 
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-    let mut buf = [0; 4096];
-
-    loop {
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(SOCKET_TIMEOUT));
-
-        control.gpio_set(0, false).await;
-        log::info!("Listening on TCP: {TCP_ENDPOINT}...");
-        if let Err(e) = socket.accept(TCP_ENDPOINT).await {
-            log::warn!("accept error: {:?}", e);
-            continue;
-        }
-
-        log::info!("Received connection from {:?}", socket.remote_endpoint());
-        control.gpio_set(0, true).await;
-
-        loop {
-            // Receives data from TCP Client
-            match socket.read(&mut buf).await {
-                Err(e) => {
-                    log::warn!("read error: {:?}", e);
-                    break;
-                }
-                Ok(0) => {
-                    log::warn!("read EOF");
-                    break;
-                }
-                Ok(idx) => {
-                    let received = &buf[..idx];
-                    // log::info!("Received {} bytes: {:?}", idx, received);
-                    let (chunks, _) = received.as_chunks::<16>(); // As chunks of len 16
-                    for chunk in chunks {
-                        let hid_instruction = HidInstruction::from_be_bytes(*chunk);
-                        tx_ch.send(hid_instruction).await;
-                    };
-                },
-            };
-        }
-    }
-}
\ No newline at end of file
+    let server = Server {
+        stack,
+        tx_ch,
+        battery_signal,
+        settings,
+        status,
+        relay,
+        connections: Signal::new(),
+    };
+    // Every slot listens on the same port, each connection takes one
+    let slots = join_array(core::array::from_fn::<_, MAX_GLOVES, _>(|slot| server.serve(slot)));
+    let (never, _) = join(server.show_connections(&mut control), slots).await;
+    never
+}
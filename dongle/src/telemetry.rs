@@ -0,0 +1,67 @@
+//! Glove telemetry for host-side apps, such as the 3D plotter, on a third serial port
+//! apart from the log. Each sample is a CSV line:
+//! `slot,timestamp_ms,qw,qx,qy,qz,ax,ay,az,gx,gy,gz,mx,my,mz,thumb,index,middle,ring,pinky,tap`
+//! with the quaternion in Q14 and the raw sensor readings. Gloves only stream
+//! while their `telemetry_rate` is set.
+
+use core::fmt::Write;
+
+use embassy_sync::{
+    channel::Channel,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use heapless::String;
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::{protocol::Telemetry, cli::write_all};
+
+use crate::cli::CliDevice;
+
+pub const TELEMETRY_PACKET_SIZE: u16 = 64;
+/// Samples held while the host is slow to read, newer ones are dropped past it
+const TELEMETRY_QUEUE: usize = 8;
+const LINE_SIZE: usize = 160;
+
+/// Same CDC ACM class as the command line
+pub type TelemetryDevice = CliDevice;
+
+static SAMPLES: Channel<CriticalSectionRawMutex, (usize, Telemetry), TELEMETRY_QUEUE> = Channel::new();
+
+/// Queues a sample of the glove in `slot`, without waiting for the host
+pub fn relay(slot: usize, telemetry: Telemetry) {
+    let _ = SAMPLES.try_send((slot, telemetry));
+}
+
+fn csv_line(slot: usize, telemetry: &Telemetry) -> String<LINE_SIZE> {
+    let mut line = String::new();
+    let _ = write!(line, "{},{}", slot, telemetry.timestamp_ms);
+    let words = telemetry.quaternion.iter()
+        .chain(&telemetry.accel)
+        .chain(&telemetry.gyro)
+        .chain(&telemetry.mag)
+        .map(|word| *word as i32)
+        .chain(telemetry.flexes.iter().map(|flex| *flex as i32));
+    for word in words {
+        let _ = write!(line, ",{}", word);
+    }
+    let _ = write!(line, ",{}\r\n", telemetry.tap as u8);
+    line
+}
+
+
+#[embassy_executor::task]
+pub async fn telemetry_task(mut class: TelemetryDevice) -> ! {
+    loop {
+        class.wait_connection().await;
+        log::info!("Telemetry host connected");
+        // Samples queued while nobody listened are stale
+        SAMPLES.clear();
+        loop {
+            let (slot, telemetry) = SAMPLES.receive().await;
+            if let Err(e) = write_all(&mut class, csv_line(slot, &telemetry).as_bytes()).await {
+                log::info!("Telemetry host disconnected: {:?}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use {defmt_rtt as _, panic_probe as _};
+use embassy_rp::{
+    pac,
+    adc::{self, Adc, Channel, Error as AdcError},
+    gpio::Pull,
+    peripherals::PIN_29,
+};
+use embassy_sync::{
+    channel::Sender,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::{Duration, Timer};
+
+use shared::{
+    definitions::CHANNEL_SIZE,
+    protocol::{GloveMessage, BatteryStatus, HapticPattern},
+};
+
+use crate::{board::SharedAdc, haptics};
+
+const BATTERY_PERIOD: Duration  = Duration::from_secs(30);
+const BATTERY_SAMPLES: u32      = 8;
+/// Charge below which the wearer is warned with a long buzz
+const LOW_BATTERY_PERCENT: u8   = 15;
+// VSYS reaches GPIO29 through a 1/3 divider
+const VSYS_DIVIDER: u32         = 3;
+const ADC_VREF_MV: u32          = 3300;
+const ADC_MAX: u32              = 4095;
+// GPIO29 doubles as the cyw43 SPI clock while GPIO25 (its chip select) is low
+const VSYS_PIN: usize           = 29;
+const WL_CS_PIN: usize          = 25;
+const FUNCSEL_NULL: u8          = 0x1f;
+
+/// LiPo resting voltage against state of charge, from full to empty
+const LIPO_CURVE: [(u16, u8); 12] = [
+    (4200, 100), (4100, 90), (4000, 80), (3930, 70), (3870, 60), (3820, 50),
+    (3790, 40), (3770, 30), (3740, 20), (3680, 10), (3450, 5), (3300, 0),
+];
+
+
+/// Interpolates the state of charge of a LiPo cell from its voltage
+pub fn state_of_charge(millivolts: u16) -> u8 {
+    let (full_mv, _) = LIPO_CURVE[0];
+    if millivolts >= full_mv {
+        return 100;
+    }
+    for window in LIPO_CURVE.windows(2) {
+        let ((high_mv, high_pct), (low_mv, low_pct)) = (window[0], window[1]);
+        if millivolts >= low_mv {
+            let span = (millivolts - low_mv) as u32 * (high_pct - low_pct) as u32;
+            return low_pct + (span / (high_mv - low_mv) as u32) as u8;
+        }
+    }
+    0
+}
+
+/// Whether the cyw43 is deselected, so its clock line is free to be borrowed
+fn wl_deselected() -> bool {
+    pac::SIO.gpio_out(0).value().read() & (1 << WL_CS_PIN) != 0
+}
+
+/// Borrows GPIO29 from the cyw43 SPI, averages VSYS readings and gives it back.
+/// Must run inside a critical section so the cyw43 runner can't start a transfer meanwhile.
+fn blocking_read_vsys(adc: &mut Adc<'static, adc::Async>) -> Result<u32, AdcError> {
+    let ctrl = pac::IO_BANK0.gpio(VSYS_PIN).ctrl().read();
+    let pad = pac::PADS_BANK0.gpio(VSYS_PIN).read();
+
+    pac::IO_BANK0.gpio(VSYS_PIN).ctrl().write(|w| w.set_funcsel(FUNCSEL_NULL));
+    // SAFETY: the pin is handed back to the PIO before leaving the critical section
+    let mut vsys = Channel::new_pin(unsafe { PIN_29::steal() }, Pull::None);
+
+    let reading = blocking_average(adc, &mut vsys);
+
+    drop(vsys);
+    pac::PADS_BANK0.gpio(VSYS_PIN).write_value(pad);
+    pac::IO_BANK0.gpio(VSYS_PIN).ctrl().write_value(ctrl);
+
+    reading
+}
+
+fn blocking_average(adc: &mut Adc<'static, adc::Async>, channel: &mut Channel<'static>) -> Result<u32, AdcError> {
+    let mut sum = 0;
+    for _ in 0..BATTERY_SAMPLES {
+        sum += adc.blocking_read(channel)? as u32;
+    }
+    Ok(sum / BATTERY_SAMPLES)
+}
+
+/// Samples VSYS in millivolts between cyw43 transfers
+async fn sample_vsys(adc: &'static SharedAdc) -> Result<u16, AdcError> {
+    let mut adc_driver = adc.lock().await;
+    loop {
+        let reading = critical_section::with(|_| {
+            wl_deselected().then(|| blocking_read_vsys(&mut adc_driver))
+        });
+        match reading {
+            Some(raw) => return raw.map(|raw| (raw * VSYS_DIVIDER * ADC_VREF_MV / ADC_MAX) as u16),
+            // Transfer in progress, try again once it finishes
+            None => Timer::after_micros(100).await,
+        }
+    }
+}
+
+
+#[embassy_executor::task]
+pub async fn battery_task(
+    adc: &'static SharedAdc,
+    tx_ch: Sender<'static, CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE>
+) -> ! {
+    // Warns once each time the charge drops below the threshold
+    let mut warned = false;
+    loop {
+        match sample_vsys(adc).await {
+            Err(e) => log::warn!("Error {:?} while reading VSYS", e),
+            Ok(millivolts) => {
+                let status = BatteryStatus { millivolts, percent: state_of_charge(millivolts) };
+                log::info!("Battery: {} mV, {}%", status.millivolts, status.percent);
+                let low = status.percent < LOW_BATTERY_PERCENT;
+                if low && !warned {
+                    haptics::play(HapticPattern::LongBuzz);
+                }
+                warned = low;
+                tx_ch.send(GloveMessage::Battery(status)).await;
+            }
+        }
+        Timer::after(BATTERY_PERIOD).await;
+    }
+}
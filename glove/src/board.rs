@@ -0,0 +1,95 @@
+use embassy_rp::{
+    peripherals::I2C0,
+    i2c::{self, I2c},
+    adc::{self, Adc},
+};
+use embassy_sync::{
+    mutex::Mutex,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+
+// Wiring of the current board revision.
+// A revision with more sensors only needs to change this file.
+
+/// I2C0 bus shared by the MPU9250 and any external ADC
+pub type I2cBus = Mutex<CriticalSectionRawMutex, I2c<'static, I2C0, i2c::Async>>;
+/// Handle to a device sitting on the shared I2C0 bus
+pub type I2cBusDevice = I2cDevice<'static, CriticalSectionRawMutex, I2c<'static, I2C0, i2c::Async>>;
+
+/// Onboard ADC shared by the flex sensors and the battery monitor
+pub type SharedAdc = Mutex<CriticalSectionRawMutex, Adc<'static, adc::Async>>;
+
+#[cfg(not(feature = "external-adc"))]
+pub use onboard::*;
+#[cfg(feature = "external-adc")]
+pub use external::*;
+
+
+/// Three flex sensors on the RP2040 ADC pins, the only ones exposed on the Pico W
+#[cfg(not(feature = "external-adc"))]
+mod onboard {
+    use embassy_rp::{
+        Peri,
+        adc::Channel,
+        gpio::Pull,
+        peripherals::{PIN_26, PIN_27, PIN_28},
+    };
+    use crate::flexes::{Finger, OnboardAdc};
+    use super::SharedAdc;
+
+    /// Amount of flex sensors wired on the board
+    pub const FLEX_COUNT: usize = 3;
+    /// Finger wired to each flex input, in reading order
+    pub const FLEX_FINGERS: [Finger; FLEX_COUNT] = [Finger::Index, Finger::Middle, Finger::Ring];
+
+    pub type FlexInputs = OnboardAdc<'static, FLEX_COUNT>;
+
+    /// Builds the flex inputs in `FLEX_FINGERS` order
+    pub fn flex_inputs(
+        adc_driver: &'static SharedAdc,
+        index: Peri<'static, PIN_26>,   // GP26, PIN31
+        middle: Peri<'static, PIN_27>,  // GP27, PIN32
+        ring: Peri<'static, PIN_28>,    // GP28, PIN34
+    ) -> FlexInputs {
+        OnboardAdc::new(adc_driver, [
+            Channel::new_pin(index, Pull::Down),
+            Channel::new_pin(middle, Pull::Down),
+            Channel::new_pin(ring, Pull::Down),
+        ])
+    }
+}
+
+
+/// Five flex sensors on two ADS1x15 sharing the MPU9250 I2C bus
+#[cfg(feature = "external-adc")]
+mod external {
+    use crate::flexes::{Finger, Ads1x15, AdsInput, ADS_DEFAULT_ADDRESS};
+    use super::I2cBusDevice;
+
+    /// Second ADS1x15 with its ADDR pin tied to VDD
+    const ADS_SECOND_ADDRESS: u8 = 0x49;
+
+    /// Amount of flex sensors wired on the board
+    pub const FLEX_COUNT: usize = 5;
+    /// Finger wired to each flex input, in reading order
+    pub const FLEX_FINGERS: [Finger; FLEX_COUNT] = [
+        Finger::Thumb, Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky
+    ];
+    /// External ADC input of each finger, in `FLEX_FINGERS` order
+    const FLEX_ADC_INPUTS: [AdsInput; FLEX_COUNT] = [
+        AdsInput { address: ADS_DEFAULT_ADDRESS, channel: 0 },
+        AdsInput { address: ADS_DEFAULT_ADDRESS, channel: 1 },
+        AdsInput { address: ADS_DEFAULT_ADDRESS, channel: 2 },
+        AdsInput { address: ADS_DEFAULT_ADDRESS, channel: 3 },
+        AdsInput { address: ADS_SECOND_ADDRESS, channel: 0 },
+    ];
+
+    pub type FlexInputs = Ads1x15<I2cBusDevice, FLEX_COUNT>;
+
+    /// Builds the flex inputs in `FLEX_FINGERS` order
+    pub fn flex_inputs(i2c: I2cBusDevice) -> FlexInputs {
+        Ads1x15::new(i2c, FLEX_ADC_INPUTS)
+    }
+}
+
@@ -0,0 +1,131 @@
+//! Flex band calibration: while the hand opens and closes for `CALIBRATION_TIME`
+//! the sensor task records the range of every finger, then the bands of the
+//! active profile are placed within the range all fingers reach.
+
+use core::fmt::Write;
+
+use {defmt_rtt as _, panic_probe as _};
+use embassy_sync::{
+    channel::Sender,
+    signal::Signal,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::Instant;
+use heapless::String;
+
+use shared::{
+    definitions::{CALIBRATION_TIME, CHANNEL_SIZE},
+    config::{SharedSettings, ConfigKey, ConfigValue},
+    protocol::{GloveMessage, ConfigReply, ConfigRejection, HapticPattern},
+    storage::SharedStore,
+};
+
+use crate::{flexes::FingerReadings, haptics};
+
+/// Narrowest reading range that still tells an open finger from a closed one
+const MIN_SPAN: u16 = 50;
+
+/// Who waits for the result
+#[derive(Debug, Clone, Copy)]
+pub enum Requester {
+    /// Answered over the link with the request sequence number
+    Dongle(u8),
+    /// The glove command line
+    Cli,
+}
+
+static REQUEST: Signal<CriticalSectionRawMutex, Requester> = Signal::new();
+static CLI_RESULT: Signal<CriticalSectionRawMutex, Result<ConfigValue, ConfigRejection>> = Signal::new();
+
+/// Asks the sensor task to calibrate, after any calibration in progress
+pub fn start(requester: Requester) {
+    REQUEST.signal(requester);
+}
+
+/// Calibrates for the command line, giving the new bands as text
+pub async fn run_from_cli() -> Result<ConfigValue, ConfigRejection> {
+    CLI_RESULT.reset();
+    start(Requester::Cli);
+    CLI_RESULT.wait().await
+}
+
+/// Reading range of every finger while calibrating
+pub struct Calibration<const N: usize> {
+    requester: Requester,
+    started: Instant,
+    /// Highest reading, the finger open
+    open: [u16; N],
+    /// Lowest reading, the finger closed
+    closed: [u16; N],
+}
+
+impl<const N: usize> Calibration<N> {
+    /// Starts a calibration if one was asked for
+    pub fn requested() -> Option<Self> {
+        let requester = REQUEST.try_take()?;
+        log::info!("Calibrating flex bands, open and close the hand");
+        haptics::play(HapticPattern::Tick);
+        Some(Calibration { requester, started: Instant::now(), open: [0; N], closed: [u16::MAX; N] })
+    }
+
+    /// Records a sample, true once the calibration time is over
+    pub fn update(&mut self, flexes: &FingerReadings<N>) -> bool {
+        for ((open, closed), reading) in self.open.iter_mut().zip(self.closed.iter_mut()).zip(flexes) {
+            *open = (*open).max(*reading);
+            *closed = (*closed).min(*reading);
+        }
+        self.started.elapsed() >= CALIBRATION_TIME
+    }
+
+    /// Lower and upper band at a quarter and three quarters of the range every finger covers
+    fn bands(&self) -> Result<(u16, u16), ConfigRejection> {
+        let open = self.open.iter().copied().min().unwrap_or(0);
+        let closed = self.closed.iter().copied().max().unwrap_or(u16::MAX);
+        let span = open.checked_sub(closed).filter(|span| *span >= MIN_SPAN).ok_or(ConfigRejection::OutOfRange)?;
+        Ok((closed + span / 4, closed + span / 4 * 3))
+    }
+
+    /// Persists and applies the measured bands, then answers the requester
+    pub async fn finish(
+        self,
+        settings: &'static SharedSettings,
+        store: &'static SharedStore,
+        tx_ch: &Sender<'static, CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE>
+    ) {
+        let result = match self.bands() {
+            Err(e) => Err(e),
+            Ok((low_band, sup_band)) => {
+                let mut store = store.lock().await;
+                let mut updated = settings.lock(|settings| settings.borrow().clone());
+                let slot = updated.active_profile;
+                // Measured together, always in order
+                let _ = updated.set_in(slot, ConfigKey::FlexLowBand, ConfigValue::Int(low_band as u32));
+                let _ = updated.set_in(slot, ConfigKey::FlexSupBand, ConfigValue::Int(sup_band as u32));
+                match store.save(&updated, ConfigKey::FlexLowBand).and_then(|()| store.save(&updated, ConfigKey::FlexSupBand)) {
+                    Ok(()) => {
+                        settings.lock(|settings| *settings.borrow_mut() = updated);
+                        let mut text = String::new();
+                        let _ = write!(text, "flex_low_band {}, flex_sup_band {}", low_band, sup_band);
+                        Ok(ConfigValue::Text(text))
+                    }
+                    Err(e) => {
+                        log::warn!("Error {:?} while saving the flex bands", e);
+                        Err(ConfigRejection::Storage)
+                    }
+                }
+            }
+        };
+        log::info!("Calibration done: {:?}", result);
+        haptics::play(match result {
+            Ok(_) => HapticPattern::DoublePulse,
+            Err(_) => HapticPattern::LongBuzz,
+        });
+        match self.requester {
+            Requester::Dongle(seq) => {
+                let reply = ConfigReply { seq, key: ConfigKey::FlexSupBand.id(), result };
+                tx_ch.send(GloveMessage::ConfigReply(reply)).await;
+            }
+            Requester::Cli => CLI_RESULT.signal(result),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use core::fmt::Write;
+
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_time::Instant;
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::{
+    cli::{self, Command, Response, After, HELP, shown_value},
+    config::{SharedSettings, ConfigKey, ConfigValue},
+    protocol::ProfileAction,
+    storage::SharedStore,
+};
+
+use crate::{remote_config, haptics, calibration};
+
+pub type CliDevice = CdcAcmClass<'static, Driver<'static, USB>>;
+
+
+/// Updates and persists a setting the same way the dongle does through the link
+async fn set_setting(key: ConfigKey, text: &str, settings: &'static SharedSettings, store: &'static SharedStore, out: &mut Response) {
+    let value = match settings.lock(|settings| settings.borrow().get(key)).parse_as(text) {
+        Ok(value) => value,
+        Err(e) => {
+            let _ = write!(out, "error: {}\r\n", e);
+            return;
+        }
+    };
+    match remote_config::set(0, key.id(), value, settings, store).await.result {
+        // The network is brought up once while booting
+        Ok(_) if key.is_network_field() => { let _ = out.write_str("saved, applied on next boot\r\n"); }
+        Ok(value) => shown_value(key, &value, out),
+        Err(e) => { let _ = write!(out, "error: {}\r\n", e); }
+    }
+}
+
+fn write_status(settings: &'static SharedSettings, out: &mut Response) {
+    let settings = settings.lock(|settings| settings.borrow().clone());
+    let _ = write!(out, "uptime: {} s\r\n", Instant::now().as_secs());
+    let _ = write!(out, "profile: {}\r\n", settings.profile().name);
+    let _ = write!(out, "network: {}\r\n", settings.network.wifi_network);
+    let _ = write!(out, "address: {}, dongle at {}\r\n",
+        ConfigValue::Ip(settings.network.glove_ip), ConfigValue::Ip(settings.network.dongle_ip));
+}
+
+/// Runs a command on the glove itself, the `glove` target is accepted but changes nothing
+async fn execute(
+    command: Command<'_>,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore,
+    out: &mut Response
+) -> After {
+    match command {
+        Command::Help => { let _ = out.write_str(HELP); }
+        Command::Status => write_status(settings, out),
+        Command::List(_) => {
+            let current = settings.lock(|settings| settings.borrow().clone());
+            for key in ConfigKey::ALL {
+                let _ = write!(out, "{} = ", key.name());
+                shown_value(key, &current.get(key), out);
+            }
+        }
+        Command::Get(_, key) => {
+            let value = settings.lock(|settings| settings.borrow().get(key));
+            shown_value(key, &value, out);
+        }
+        Command::Set(_, key, text) => set_setting(key, text, settings, store, out).await,
+        Command::Calibrate(_) => {
+            let _ = match calibration::run_from_cli().await {
+                Ok(bands) => write!(out, "{}\r\n", bands),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            };
+        }
+        Command::Profile(_, action, name) => {
+            let _ = match remote_config::profile(0, action, name, settings, store).await.result {
+                Ok(ConfigValue::Text(names)) if action == ProfileAction::List => write!(out, "{}\r\n", names),
+                Ok(active) => write!(out, "active profile: {}\r\n", active),
+                Err(e) => write!(out, "error: {}\r\n", e),
+            };
+        }
+        Command::Buzz(_, pattern) => haptics::play(pattern),
+        Command::Pair => { let _ = out.write_str("error: pairing is done from the dongle\r\n"); }
+        Command::Reboot(_) => {
+            let _ = out.write_str("rebooting\r\n");
+            return After::Reboot;
+        }
+    }
+    After::Prompt
+}
+
+
+#[embassy_executor::task]
+pub async fn cli_task(
+    class: CliDevice,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore
+) -> ! {
+    cli::run(class, async |command, out| execute(command, settings, store, out).await).await
+}
@@ -1,26 +1,169 @@
-use embassy_rp::adc::{self, Adc, Error as AdcError};
+use embassy_rp::adc::{self, Error as AdcError};
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{I2c, Error as _, ErrorKind};
 
-pub const INDEX: usize = 0;
-pub const MIDDLE: usize = 1;
-pub const RING: usize = 2;
-pub type FingerReadings = [u16; 3];
+use shared::config::KEYMAP_SIZE;
 
-pub struct FingerFlexes<'a> {
-    adc_driver: Adc<'a, adc::Async>,
-    thumb_flex: adc::Channel<'a>,
-    index_flex: adc::Channel<'a>,
-    middle_flex: adc::Channel<'a>,
+use crate::board::SharedAdc;
+
+/// Maximum amount of flex sensors a glove can carry, one per finger
+pub const MAX_FINGERS: usize = KEYMAP_SIZE;
+pub type FingerReadings<const N: usize> = [u16; N];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Pinky,
+}
+
+impl Finger {
+    /// Position of the finger in a `Keymap`, which goes from thumb to pinky
+    pub fn keymap_index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Debug)]
+pub enum FlexError {
+    Adc(AdcError),
+    I2c(ErrorKind),
+    /// External ADC never finished the conversion
+    Timeout,
+}
+
+/// Source of raw flex readings, scaled to the 12 bit range of the onboard ADC
+pub trait FlexBackend<const N: usize> {
+    async fn read(&mut self) -> Result<FingerReadings<N>, FlexError>;
+}
+
+
+/// Flex sensors wired straight to the RP2040 ADC pins
+pub struct OnboardAdc<'a, const N: usize> {
+    adc_driver: &'a SharedAdc,
+    channels: [adc::Channel<'a>; N],
+}
+
+impl<'a, const N: usize> OnboardAdc<'a, N> {
+    pub fn new(adc_driver: &'a SharedAdc, channels: [adc::Channel<'a>; N]) -> Self {
+        OnboardAdc { adc_driver, channels }
+    }
+}
+
+impl<'a, const N: usize> FlexBackend<N> for OnboardAdc<'a, N> {
+    async fn read(&mut self) -> Result<FingerReadings<N>, FlexError> {
+        let mut adc_driver = self.adc_driver.lock().await;
+        let mut readings = [0; N];
+        for (reading, channel) in readings.iter_mut().zip(self.channels.iter_mut()) {
+            *reading = adc_driver.read(channel).await.map_err(FlexError::Adc)?;
+        }
+        Ok(readings)
+    }
+}
+
+
+// ADS1x15 registers and config fields
+const ADS_CONVERSION_REG: u8        = 0x00;
+const ADS_CONFIG_REG: u8            = 0x01;
+const ADS_CONFIG_OS: u16            = 1 << 15;      // Start conversion / conversion done
+const ADS_CONFIG_MUX_SINGLE: u16    = 0b100 << 12;  // AINx against GND, x on the lower two bits
+const ADS_CONFIG_PGA_4V096: u16     = 0b001 << 9;
+const ADS_CONFIG_MODE_SINGLE: u16   = 1 << 8;
+const ADS_CONFIG_DR_MAX: u16        = 0b111 << 5;   // 3300 SPS on ADS1015, 860 SPS on ADS1115
+const ADS_CONFIG_COMP_DISABLE: u16  = 0b11;
+const ADS_FULL_SCALE_MV: i32        = 4096;
+const ADS_POLL_US: u64              = 100;
+const ADS_POLL_ATTEMPTS: u8         = 20;
+// Onboard ADC range the flex bands are tuned for
+const ONBOARD_VREF_MV: i32          = 3300;
+const ONBOARD_MAX: i32              = 4095;
+
+/// Default I2C address of an ADS1x15 (ADDR pin tied to GND)
+pub const ADS_DEFAULT_ADDRESS: u8 = 0x48;
+
+/// Single ended input of an ADS1015/ADS1115 sitting on the I2C bus
+#[derive(Debug, Clone, Copy)]
+pub struct AdsInput {
+    pub address: u8,
+    pub channel: u8,
+}
+
+/// Flex sensors wired to one or more ADS1015/ADS1115 external ADCs.
+/// Both share the same register layout, the ADS1015 being left justified,
+/// so readings are scaled the same way.
+pub struct Ads1x15<I, const N: usize> {
+    i2c: I,
+    inputs: [AdsInput; N],
+}
+
+impl<I: I2c, const N: usize> Ads1x15<I, N> {
+    pub fn new(i2c: I, inputs: [AdsInput; N]) -> Self {
+        Ads1x15 { i2c, inputs }
+    }
+
+    async fn read_input(&mut self, input: AdsInput) -> Result<u16, FlexError> {
+        let config = ADS_CONFIG_OS
+            | ADS_CONFIG_MUX_SINGLE | ((input.channel as u16 & 0b11) << 12)
+            | ADS_CONFIG_PGA_4V096
+            | ADS_CONFIG_MODE_SINGLE
+            | ADS_CONFIG_DR_MAX
+            | ADS_CONFIG_COMP_DISABLE;
+        let [high, low] = config.to_be_bytes();
+        self.i2c.write(input.address, &[ADS_CONFIG_REG, high, low]).await
+            .map_err(|e| FlexError::I2c(e.kind()))?;
+
+        // Single shot conversion clears OS until it finishes
+        let mut register = [0; 2];
+        for _ in 0..ADS_POLL_ATTEMPTS {
+            Timer::after_micros(ADS_POLL_US).await;
+            self.i2c.write_read(input.address, &[ADS_CONFIG_REG], &mut register).await
+                .map_err(|e| FlexError::I2c(e.kind()))?;
+            if u16::from_be_bytes(register) & ADS_CONFIG_OS == 0 { continue; }
+
+            self.i2c.write_read(input.address, &[ADS_CONVERSION_REG], &mut register).await
+                .map_err(|e| FlexError::I2c(e.kind()))?;
+            return Ok(to_onboard_scale(i16::from_be_bytes(register)));
+        }
+        Err(FlexError::Timeout)
+    }
 }
 
-impl<'a> FingerFlexes<'a> {
-    pub fn new(adc_driver: Adc<'a, adc::Async>, thumb_flex: adc::Channel<'a>, index_flex: adc::Channel<'a>, middle_flex: adc::Channel<'a>,) -> Self {
-        FingerFlexes {adc_driver, thumb_flex, index_flex, middle_flex}
+impl<I: I2c, const N: usize> FlexBackend<N> for Ads1x15<I, N> {
+    async fn read(&mut self) -> Result<FingerReadings<N>, FlexError> {
+        let mut readings = [0; N];
+        for (reading, input) in readings.iter_mut().zip(self.inputs) {
+            *reading = self.read_input(input).await?;
+        }
+        Ok(readings)
+    }
+}
+
+/// Converts an ADS1x15 reading into the onboard ADC 12 bit range
+fn to_onboard_scale(raw: i16) -> u16 {
+    let millivolts = raw.max(0) as i32 * ADS_FULL_SCALE_MV / (i16::MAX as i32 + 1);
+    (millivolts * ONBOARD_MAX / ONBOARD_VREF_MV).min(ONBOARD_MAX) as u16
+}
+
+
+pub struct FingerFlexes<B, const N: usize> {
+    backend: B,
+    fingers: [Finger; N],
+}
+
+impl<B: FlexBackend<N>, const N: usize> FingerFlexes<B, N> {
+    /// Each backend reading belongs to the finger at the same position in `fingers`
+    pub fn new(backend: B, fingers: [Finger; N]) -> Self {
+        const { assert!(N <= MAX_FINGERS, "More flex sensors than fingers") };
+        FingerFlexes { backend, fingers }
+    }
+
+    pub fn fingers(&self) -> &[Finger; N] {
+        &self.fingers
     }
 
-    pub async fn read(&mut self) -> Result<FingerReadings, AdcError> {
-        let thumb   = self.adc_driver.read(&mut self.thumb_flex).await?;
-        let index   = self.adc_driver.read(&mut self.index_flex).await?;
-        let middle  = self.adc_driver.read(&mut self.middle_flex).await?;
-        Ok([thumb, index, middle])
+    pub async fn read(&mut self) -> Result<FingerReadings<N>, FlexError> {
+        self.backend.read().await
     }
 }
@@ -0,0 +1,42 @@
+use embassy_time::Instant;
+
+use shared::definitions::{PROFILE_SWITCH_TAPS, TAP_GAP};
+
+/// Detects a quick series of finger taps, used to switch profiles
+pub struct TapCounter {
+    taps: u8,
+    last_tap: Instant,
+    tapping: bool,
+}
+
+impl Default for TapCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TapCounter {
+    pub fn new() -> Self {
+        TapCounter { taps: 0, last_tap: Instant::now(), tapping: false }
+    }
+
+    /// Feeds the tap state, true once `PROFILE_SWITCH_TAPS` taps land at most `TAP_GAP` apart
+    pub fn update(&mut self, tap: bool) -> bool {
+        let pressed = tap && !self.tapping;
+        self.tapping = tap;
+        if !pressed {
+            return false;
+        }
+
+        self.taps = match self.last_tap.elapsed() > TAP_GAP {
+            true => 1,
+            false => self.taps + 1,
+        };
+        self.last_tap = Instant::now();
+        if self.taps < PROFILE_SWITCH_TAPS {
+            return false;
+        }
+        self.taps = 0;
+        true
+    }
+}
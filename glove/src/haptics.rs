@@ -0,0 +1,105 @@
+use {defmt_rtt as _, panic_probe as _};
+use embassy_rp::pwm::{Config as PwmConfig, Pwm};
+use embassy_sync::{
+    channel::Channel,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::{Duration, Timer};
+use portable_atomic::{AtomicU8, Ordering};
+
+use shared::{
+    protocol::HapticPattern,
+    custom_hid::{LED_NUM_LOCK, LED_CAPS_LOCK, LED_SCROLL_LOCK},
+};
+
+// 125 MHz system clock over 5000 counts drives the motor at 25 kHz, above hearing
+const PWM_TOP: u16              = 4999;
+const MOTOR_DUTY_PERCENT: u32   = 80;
+/// Patterns waiting while the motor plays, more are dropped
+const PATTERN_QUEUE: usize      = 4;
+/// Keeps queued patterns apart
+const PATTERN_GAP: Duration     = Duration::from_millis(100);
+
+const TICK: &[(bool, Duration)] = &[
+    (true, Duration::from_millis(30)),
+];
+const DOUBLE_PULSE: &[(bool, Duration)] = &[
+    (true, Duration::from_millis(40)),
+    (false, Duration::from_millis(80)),
+    (true, Duration::from_millis(40)),
+];
+const LONG_BUZZ: &[(bool, Duration)] = &[
+    (true, Duration::from_millis(400)),
+];
+
+/// Patterns triggered anywhere on the glove, played one after another
+static PATTERNS: Channel<CriticalSectionRawMutex, HapticPattern, PATTERN_QUEUE> = Channel::new();
+
+/// Queues a vibration without waiting for the motor
+pub fn play(pattern: HapticPattern) {
+    if PATTERNS.try_send(pattern).is_err() {
+        log::warn!("Haptic queue full, dropped {:?}", pattern);
+    }
+}
+
+/// Host keyboard LEDs last felt
+static KEYBOARD_LEDS: AtomicU8 = AtomicU8::new(0);
+
+/// Lock keys the host turns on buzz twice, turning them off ticks once
+pub fn show_keyboard_leds(leds: u8) {
+    let previous = KEYBOARD_LEDS.swap(leds, Ordering::Relaxed);
+    let changed = (previous ^ leds) & (LED_NUM_LOCK | LED_CAPS_LOCK | LED_SCROLL_LOCK);
+    if changed & leds != 0 {
+        play(HapticPattern::DoublePulse);
+    } else if changed != 0 {
+        play(HapticPattern::Tick);
+    }
+}
+
+/// Motor on or off spans of a pattern
+fn steps(pattern: HapticPattern) -> &'static [(bool, Duration)] {
+    match pattern {
+        HapticPattern::Tick => TICK,
+        HapticPattern::DoublePulse => DOUBLE_PULSE,
+        HapticPattern::LongBuzz => LONG_BUZZ,
+    }
+}
+
+/// Vibration motor driven through a transistor by a PWM output
+pub struct Motor {
+    pwm: Pwm<'static>,
+    config: PwmConfig,
+}
+
+impl Motor {
+    pub fn new(pwm: Pwm<'static>) -> Self {
+        let mut config = PwmConfig::default();
+        config.top = PWM_TOP;
+        config.compare_a = 0;
+        let mut motor = Motor { pwm, config };
+        motor.set(false);
+        motor
+    }
+
+    fn set(&mut self, on: bool) {
+        self.config.compare_a = match on {
+            true => ((PWM_TOP as u32 + 1) * MOTOR_DUTY_PERCENT / 100) as u16,
+            false => 0,
+        };
+        self.pwm.set_config(&self.config);
+    }
+}
+
+
+#[embassy_executor::task]
+pub async fn haptics_task(mut motor: Motor) -> ! {
+    loop {
+        let pattern = PATTERNS.receive().await;
+        for &(on, span) in steps(pattern) {
+            motor.set(on);
+            Timer::after(span).await;
+        }
+        motor.set(false);
+        Timer::after(PATTERN_GAP).await;
+    }
+}
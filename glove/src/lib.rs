@@ -1,7 +1,17 @@
 #![no_std]
 #![no_main]
+#![allow(async_fn_in_trait)]
 
 pub mod sensors;
 pub mod blinker;
 pub mod tcp_client;
-pub mod flexes;
\ No newline at end of file
+pub mod flexes;
+pub mod board;
+pub mod battery;
+pub mod power;
+pub mod remote_config;
+pub mod gestures;
+pub mod usb;
+pub mod cli;
+pub mod haptics;
+pub mod calibration;
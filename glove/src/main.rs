@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 
+use core::cell::RefCell;
 use defmt::*;
 use embassy_time::Delay;
 use {defmt_rtt as _, panic_probe as _};
@@ -12,12 +13,16 @@ use embassy_rp::{
     pio::{self, Pio}, 
     i2c::{self, I2c},
     usb::{self, Driver},
-    adc::{self, Adc, Config as AdcConfig}
+    adc::{self, Adc, Config as AdcConfig},
+    flash::Flash,
+    pwm::{Config as PwmConfig, Pwm},
 };
 use embassy_sync::{
     channel::Channel,
-    blocking_mutex::raw::CriticalSectionRawMutex,
+    mutex::Mutex,
+    blocking_mutex::{Mutex as BlockingMutex, raw::CriticalSectionRawMutex},
 };
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
 
 use static_cell::StaticCell;
@@ -26,14 +31,22 @@ use mpu9250_async::{address::Address, sensor_async::Mpu9250};
 
 use shared::{
     definitions::CHANNEL_SIZE,
-    custom_hid::HidInstruction,
+    protocol::GloveMessage,
+    config::SharedSettings,
+    storage::{SharedStore, mount_store},
 };
 
 use glove::{
     // blinker::blink_task,
     sensors::sensor_processing,
     flexes::FingerFlexes,
+    board::{I2cBus, SharedAdc, FLEX_FINGERS, flex_inputs},
+    battery::battery_task,
+    power::WakeOnMotion,
     tcp_client::{network_config, tcp_client_task},
+    usb::{LoggerDevice, config_usb},
+    cli::cli_task,
+    haptics::{Motor, haptics_task},
 };
 
 
@@ -58,8 +71,13 @@ async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'sta
 }
 
 #[embassy_executor::task]
-pub async fn logger_task(driver: Driver<'static, USB>) {
-    embassy_usb_logger::run!(1024, log::LevelFilter::Info, driver);
+async fn logger_task(logger: LoggerDevice) {
+    embassy_usb_logger::with_class!(1024, log::LevelFilter::Info, logger).await
+}
+
+#[embassy_executor::task]
+async fn usb_task(mut usb: embassy_usb::UsbDevice<'static, Driver<'static, USB>>) -> ! {
+    usb.run().await
 }
 
 
@@ -81,9 +99,21 @@ async fn main(spawner: Spawner) {
         p.DMA_CH0,
     );
 
-    // Set usb_logger
+    // Config USB port, the log and a command line
     let driver = Driver::new(p.USB, Irqs);
-    unwrap!(spawner.spawn(logger_task(driver)));
+    let (usb, logger, cli) = config_usb(driver);
+    unwrap!(spawner.spawn(usb_task(usb)));
+    unwrap!(spawner.spawn(logger_task(logger)));
+
+    // Load persisted settings
+    let mut store = mount_store(Flash::new_blocking(p.FLASH));
+    static SETTINGS: StaticCell<SharedSettings> = StaticCell::new();
+    let settings = SETTINGS.init(BlockingMutex::new(RefCell::new(store.load())));
+    static STORE: StaticCell<SharedStore> = StaticCell::new();
+    let store = STORE.init(Mutex::new(store));
+
+    // Launch command line on the second serial port, network settings are provisioned there
+    unwrap!(spawner.spawn(cli_task(cli, settings, store)));
     
     // cyw43 wifi chip init
     let fw = include_bytes!("../../shared/cyw43-firmware/43439A0.bin");
@@ -101,33 +131,50 @@ async fn main(spawner: Spawner) {
 
     // unwrap!(spawner.spawn(blink_task(control)));
 
-    let (stack, runner) = network_config(net_device);
+    let glove_ip = settings.lock(|settings| settings.borrow().network.glove_ip);
+    let (stack, runner) = network_config(net_device, glove_ip);
     unwrap!(spawner.spawn(net_task(runner)));
 
-    static CHANNEL: Channel<CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE> = Channel::new();
+    static CHANNEL: Channel<CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE> = Channel::new();
     let tx_ch = CHANNEL.sender();
     let rx_ch = CHANNEL.receiver();
 
-    unwrap!(spawner.spawn(tcp_client_task(control, stack, rx_ch)));
+    unwrap!(spawner.spawn(tcp_client_task(control, stack, rx_ch, settings, store)));
 
-    // Instantiate ADC flex sensors
-    let adc_driver   = Adc::new(p.ADC, Irqs, AdcConfig::default());
-    let index_flex      = adc::Channel::new_pin(p.PIN_26, Pull::Down);
-    let middle_flex     = adc::Channel::new_pin(p.PIN_27, Pull::Down);
-    let ring_flex       = adc::Channel::new_pin(p.PIN_28, Pull::Down);
+    // Instantiate vibration motor
+    let motor_pwm = Pwm::new_output_a(p.PWM_SLICE7, p.PIN_14, PwmConfig::default());  // GP14, PIN19
+    unwrap!(spawner.spawn(haptics_task(Motor::new(motor_pwm))));
 
-    let finger_flexes = FingerFlexes::new(adc_driver, index_flex, middle_flex, ring_flex);
+    // Instantiate shared I2C bus
+    let sda = p.PIN_20; // GP20, PIN26
+    let scl = p.PIN_21; // GP21, PIN27
+
+    let i2c_config = i2c::Config::default();
+    static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+    let i2c_bus = I2C_BUS.init(Mutex::new(I2c::new_async(p.I2C0, scl, sda, Irqs, i2c_config)));
+
+    // Instantiate onboard ADC, shared by flex sensors and battery monitor
+    static ADC: StaticCell<SharedAdc> = StaticCell::new();
+    let adc_driver = ADC.init(Mutex::new(Adc::new(p.ADC, Irqs, AdcConfig::default())));
+    unwrap!(spawner.spawn(battery_task(adc_driver, tx_ch)));
+
+    // Instantiate flex sensors
+    #[cfg(not(feature = "external-adc"))]
+    let flex_backend = flex_inputs(adc_driver, p.PIN_26, p.PIN_27, p.PIN_28);
+    #[cfg(feature = "external-adc")]
+    let flex_backend = flex_inputs(I2cDevice::new(i2c_bus));
+
+    let finger_flexes = FingerFlexes::new(flex_backend, FLEX_FINGERS);
 
     // Instantiate Finger tap
     let mut finger_tap = Input::new(p.PIN_16, Pull::Down);
     finger_tap.set_schmitt(true);
 
     // Instantiate mpu sensor
-    let sda = p.PIN_20; // GP20, PIN26
-    let scl = p.PIN_21; // GP21, PIN27
+    let mpu = Mpu9250::new(I2cDevice::new(i2c_bus), Address::default(), &mut Delay).await.unwrap();
 
-    let i2c_config = i2c::Config::default();
-    let i2c_bus = I2c::new_async(p.I2C0, scl, sda, Irqs, i2c_config);
-    let mpu = Mpu9250::new(i2c_bus, Address::default(), &mut Delay).await.unwrap();
-    unwrap!(spawner.spawn(sensor_processing(mpu, finger_flexes, finger_tap, tx_ch)));
+    // Instantiate mpu wake on motion
+    let motion_int = Input::new(p.PIN_17, Pull::Down);  // GP17, PIN22
+    let wom = WakeOnMotion::new(I2cDevice::new(i2c_bus), motion_int);
+    unwrap!(spawner.spawn(sensor_processing(mpu, finger_flexes, finger_tap, wom, tx_ch, settings, store)));
 }
@@ -0,0 +1,180 @@
+use {defmt_rtt as _, panic_probe as _};
+use embassy_rp::gpio::Input;
+use embassy_sync::{
+    channel::Sender,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+use embassy_time::{Duration, Instant};
+use embassy_futures::select::select;
+use embedded_hal_async::i2c::{I2c, Error as _, ErrorKind};
+use mpu9250_async::gyro::Gyro;
+
+use shared::{
+    definitions::CHANNEL_SIZE,
+    protocol::{GloveMessage, PowerState},
+    config::PowerSettings,
+};
+
+use crate::board::I2cBusDevice;
+
+// MPU9250 address with AD0 low, same as `Address::default()`
+const MPU_ADDRESS: u8           = 0x68;
+// MPU9250 registers used by wake on motion
+const ACCEL_CONFIG2: u8         = 0x1D;
+const LP_ACCEL_ODR: u8          = 0x1E;
+const WOM_THR: u8               = 0x1F;
+const INT_PIN_CFG: u8           = 0x37;
+const INT_ENABLE: u8            = 0x38;
+const INT_STATUS: u8            = 0x3A;
+const MOT_DETECT_CTRL: u8       = 0x69;
+const PWR_MGMT_1: u8            = 0x6B;
+const PWR_MGMT_2: u8            = 0x6C;
+// Register values
+const ACCEL_DLPF_184HZ: u8      = 0x01;
+const LP_ACCEL_ODR_15HZ: u8     = 0x06;
+const INT_LATCH_ANY_READ: u8    = 0x30; // Latch INT high until any register is read
+const INT_WOM_EN: u8            = 0x40;
+const ACCEL_INTEL_EN_CMP: u8    = 0xC0; // Compare every sample against the previous one
+const PWR_CYCLE: u8             = 0x20;
+const GYRO_DISABLED: u8         = 0x07;
+const WOM_LSB_MG: u16           = 4;
+
+
+/// Tells whether the glove has been kept still long enough to sleep
+pub struct IdleDetector {
+    still_since: Instant,
+}
+
+impl Default for IdleDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdleDetector {
+    pub fn new() -> Self {
+        IdleDetector { still_since: Instant::now() }
+    }
+
+    pub fn reset(&mut self) {
+        self.still_since = Instant::now();
+    }
+
+    /// Feeds a new sample, any rotation, finger tap or input held by a closed finger
+    /// counts as activity
+    pub fn is_idle(&mut self, gyro: &Gyro, tap: bool, held: bool, power: &PowerSettings) -> bool {
+        let moving = [gyro.x(), gyro.y(), gyro.z()]
+            .iter()
+            .any(|rate| (*rate as f32).abs() > power.idle_gyro_threshold);
+        if moving || tap || held {
+            self.reset();
+        }
+        self.still_since.elapsed() >= Duration::from_secs(power.idle_timeout_s as u64)
+    }
+}
+
+
+/// Registers overwritten by wake on motion, restored when leaving it
+#[derive(Default)]
+struct SavedRegisters {
+    accel_config2: u8,
+    int_pin_cfg: u8,
+    int_enable: u8,
+    pwr_mgmt_1: u8,
+    pwr_mgmt_2: u8,
+}
+
+/// MPU9250 low power accelerometer mode that raises INT on motion.
+/// Drives the registers directly through its own handle on the shared bus,
+/// as the sensor driver doesn't expose them.
+pub struct WakeOnMotion {
+    i2c: I2cBusDevice,
+    motion_int: Input<'static>,
+    saved: SavedRegisters,
+}
+
+impl WakeOnMotion {
+    pub fn new(i2c: I2cBusDevice, motion_int: Input<'static>) -> Self {
+        WakeOnMotion { i2c, motion_int, saved: SavedRegisters::default() }
+    }
+
+    /// Waits for the MPU9250 INT pin, it stays latched high until a register is read
+    pub async fn wait_for_motion(&mut self) {
+        self.motion_int.wait_for_high().await
+    }
+
+    async fn read(&mut self, register: u8) -> Result<u8, ErrorKind> {
+        let mut value = [0];
+        self.i2c.write_read(MPU_ADDRESS, &[register], &mut value).await.map_err(|e| e.kind())?;
+        Ok(value[0])
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), ErrorKind> {
+        self.i2c.write(MPU_ADDRESS, &[register, value]).await.map_err(|e| e.kind())
+    }
+
+    /// Turns the gyroscope off and cycles the accelerometer at low rate
+    pub async fn enter(&mut self, threshold_mg: u16) -> Result<(), ErrorKind> {
+        self.saved = SavedRegisters {
+            accel_config2:  self.read(ACCEL_CONFIG2).await?,
+            int_pin_cfg:    self.read(INT_PIN_CFG).await?,
+            int_enable:     self.read(INT_ENABLE).await?,
+            pwr_mgmt_1:     self.read(PWR_MGMT_1).await?,
+            pwr_mgmt_2:     self.read(PWR_MGMT_2).await?,
+        };
+        let threshold = (threshold_mg / WOM_LSB_MG).min(u8::MAX as u16) as u8;
+
+        self.write(PWR_MGMT_1, 0x00).await?;
+        self.write(PWR_MGMT_2, GYRO_DISABLED).await?;
+        self.write(ACCEL_CONFIG2, ACCEL_DLPF_184HZ).await?;
+        // Keeps the magnetometer bypass bit
+        self.write(INT_PIN_CFG, self.saved.int_pin_cfg | INT_LATCH_ANY_READ).await?;
+        self.write(INT_ENABLE, INT_WOM_EN).await?;
+        self.write(MOT_DETECT_CTRL, ACCEL_INTEL_EN_CMP).await?;
+        self.write(WOM_THR, threshold).await?;
+        self.write(LP_ACCEL_ODR, LP_ACCEL_ODR_15HZ).await?;
+        self.write(PWR_MGMT_1, PWR_CYCLE).await?;
+        // Drops any interrupt latched while configuring
+        self.read(INT_STATUS).await?;
+        Ok(())
+    }
+
+    /// Restores the full rate configuration and clears the interrupt
+    pub async fn exit(&mut self) -> Result<(), ErrorKind> {
+        self.write(PWR_MGMT_1, self.saved.pwr_mgmt_1).await?;
+        self.write(PWR_MGMT_2, self.saved.pwr_mgmt_2).await?;
+        self.write(MOT_DETECT_CTRL, 0x00).await?;
+        self.write(INT_ENABLE, self.saved.int_enable).await?;
+        self.write(INT_PIN_CFG, self.saved.int_pin_cfg).await?;
+        self.write(ACCEL_CONFIG2, self.saved.accel_config2).await?;
+        self.read(INT_STATUS).await?;
+        Ok(())
+    }
+}
+
+
+/// Puts the glove to sleep until the MPU9250 detects motion or the fingers tap.
+/// The dongle is told about both transitions, which also switch the cyw43 power mode,
+/// and releases whatever the glove held when it falls asleep.
+pub async fn sleep_until_motion(
+    wom: &mut WakeOnMotion,
+    finger_tap: &mut Input<'static>,
+    tx_ch: &Sender<'static, CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE>,
+    power: &PowerSettings
+) {
+    log::info!("Glove idle, going to sleep");
+    if let Err(e) = wom.enter(power.wom_threshold_mg).await {
+        log::warn!("Error {:?} while enabling wake on motion", e);
+        return;
+    }
+    tx_ch.send(GloveMessage::Power(PowerState::Sleep)).await;
+
+    // Both pins are awaited as interrupts, the core idles in between
+    select(wom.wait_for_motion(), finger_tap.wait_for_high()).await;
+
+    if let Err(e) = wom.exit().await {
+        log::warn!("Error {:?} while disabling wake on motion", e);
+    }
+    log::info!("Motion detected, waking up");
+    tx_ch.send(GloveMessage::Power(PowerState::Active)).await;
+}
@@ -0,0 +1,110 @@
+use core::fmt::Write;
+
+use {defmt_rtt as _, panic_probe as _};
+use heapless::String;
+
+use shared::{
+    config::{ConfigKey, ConfigValue, SharedSettings, Settings, ConfigError, WIFI_PASSWORD_LEN},
+    protocol::{ConfigReply, ConfigRejection, ProfileAction},
+    storage::SharedStore,
+};
+
+/// Answers a configuration read from the dongle
+pub fn get(seq: u8, key: u8, settings: &'static SharedSettings) -> ConfigReply {
+    let result = ConfigKey::from_id(key)
+        .map(|key| settings.lock(|settings| settings.borrow().get(key)))
+        .ok_or(ConfigRejection::UnknownKey);
+    ConfigReply { seq, key, result }
+}
+
+/// Persists a configuration change from the dongle, then applies it.
+/// Running tasks pick it up on their next settings read.
+pub async fn set(
+    seq: u8,
+    key: u8,
+    value: ConfigValue,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore
+) -> ConfigReply {
+    let Some(config_key) = ConfigKey::from_id(key) else {
+        return ConfigReply { seq, key, result: Err(ConfigRejection::UnknownKey) };
+    };
+    let mut store = store.lock().await;
+    let mut updated = settings.lock(|settings| settings.borrow().clone());
+    let result = match updated.set(config_key, value) {
+        Err(e) => Err(e.into()),
+        Ok(()) => match store.save(&updated, config_key) {
+            Ok(()) => {
+                let value = updated.get(config_key);
+                settings.lock(|settings| *settings.borrow_mut() = updated);
+                Ok(value)
+            }
+            Err(e) => {
+                log::warn!("Error {:?} while saving {}", e, config_key.name());
+                Err(ConfigRejection::Storage)
+            }
+        },
+    };
+    log::info!("Set {} from dongle: {:?}", config_key.name(), result);
+    ConfigReply { seq, key, result }
+}
+
+/// Stored profile names separated by spaces, the active one marked with `*`
+fn profile_names(settings: &Settings) -> String<WIFI_PASSWORD_LEN> {
+    let mut names = String::new();
+    for (slot, profile) in settings.profiles.iter().enumerate().filter(|(_, profile)| profile.is_used()) {
+        let marker = if slot == settings.active_profile { "*" } else { "" };
+        if !names.is_empty() {
+            let _ = names.push(' ');
+        }
+        // Always fits, names are short and slots few
+        let _ = write!(names, "{}{}", profile.name, marker);
+    }
+    names
+}
+
+/// Runs a profile command from the dongle, replying with the active profile name
+pub async fn profile(
+    seq: u8,
+    action: ProfileAction,
+    name: &str,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore
+) -> ConfigReply {
+    let key = ConfigKey::ActiveProfile.id();
+    if action == ProfileAction::List {
+        let names = settings.lock(|settings| profile_names(&settings.borrow()));
+        return ConfigReply { seq, key, result: Ok(ConfigValue::Text(names)) };
+    }
+
+    let mut store = store.lock().await;
+    let mut updated = settings.lock(|settings| settings.borrow().clone());
+    let applied: Result<(), ConfigError> = match action {
+        ProfileAction::List => Ok(()),
+        ProfileAction::Load => updated.load_profile(name),
+        ProfileAction::Save => updated.save_profile(name).map(|_| ()),
+        ProfileAction::Delete => updated.delete_profile(name),
+    };
+    if let Err(e) = applied {
+        return ConfigReply { seq, key, result: Err(e.into()) };
+    }
+
+    log::info!("Profile {:?} {} from dongle", action, name);
+    let saved = match action {
+        // Only the active slot changes
+        ProfileAction::Load => store.save(&updated, ConfigKey::ActiveProfile),
+        _ => store.rewrite(&updated),
+    };
+    let result = match saved {
+        Ok(()) => {
+            let active = updated.get(ConfigKey::ProfileName);
+            settings.lock(|settings| *settings.borrow_mut() = updated);
+            Ok(active)
+        }
+        Err(e) => {
+            log::warn!("Error {:?} while saving profiles", e);
+            Err(ConfigRejection::Storage)
+        }
+    };
+    ConfigReply { seq, key, result }
+}
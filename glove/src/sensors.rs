@@ -5,11 +5,7 @@ use embassy_time::{
     Duration, Timer, Instant,
     //Delay,
 };
-use embassy_rp::{
-    peripherals::I2C0,
-    gpio::Input,
-    i2c::{self, I2c},
-};
+use embassy_rp::gpio::Input;
 use embassy_sync::{
     channel::Sender,
     blocking_mutex::raw::CriticalSectionRawMutex,
@@ -24,31 +20,37 @@ use usbd_hid::descriptor::{
     KeyboardReport, KeyboardUsage,
     MediaKeyboardReport, MediaKey,
 };
-use libm::{atan2f, powf, sqrtf, roundf};
+use libm::{atan2f, powf, sqrtf, roundf, sinf, cosf};
 
 use shared::{
     definitions::{
-        READ_FREQ, DELTA_TIME, PADDING_FREQ,
+        READ_FREQ, PADDING_FREQ, SCROLL_MULTIPLIER,
         CHANNEL_SIZE,
-        DEAD_ZONE,
-        ROLL_SENS, PITCH_SENS, WHEEL_SENS, PAN_SENS,
     },
-    custom_hid::HidInstruction,
+    custom_hid::{HidInstruction, KeyBitmap, GamepadReport, GAMEPAD_TAP_BUTTON},
+    protocol::{GloveMessage, HapticPattern, Telemetry, TELEMETRY_FINGERS},
+    config::{SharedSettings, PointerSettings, FlexSettings, Keymap, FingerAction, ConfigKey, OutputMode},
+    storage::SharedStore,
 };
 
-use crate::flexes::{
-    FingerFlexes,FingerReadings,
-    INDEX, MIDDLE, RING
+use crate::{
+    board::{FLEX_COUNT, FlexInputs, I2cBusDevice},
+    flexes::{Finger, FingerFlexes, FingerReadings, FlexBackend},
+    power::{IdleDetector, WakeOnMotion, sleep_until_motion},
+    gestures::TapCounter,
+    calibration::Calibration,
+    haptics,
 };
 
 const OPENED: bool = false;
 const CLOSED: bool = true;
 const LEFT_CLICK: u8 = 1;
 const RIGHT_CLICK: u8 = 2;
+const MIDDLE_CLICK: u8 = 4;
 
 
 /*
-async fn calibrate_mpu(mpu: &mut Mpu9250<I2c<'static, I2C0, i2c::Async>>) {
+async fn calibrate_mpu(mpu: &mut Mpu9250<I2cBusDevice>) {
     let calibration_params = CalibrationParameters::new(
         mpu9250_async::accel::AccelFullScale::G4,
         mpu9250_async::gyro::GyroFullScale::Deg2000,
@@ -62,7 +64,7 @@ async fn calibrate_mpu(mpu: &mut Mpu9250<I2c<'static, I2C0, i2c::Async>>) {
 */
 
 /*
-pub async fn configure_mpu(mpu: &mut Mpu9250<I2c<'static, I2C0, i2c::Async>>) {
+pub async fn configure_mpu(mpu: &mut Mpu9250<I2cBusDevice>) {
     // Initialize DMP
     log::info!("Initializing DMP");
     // mpu.initialize_dmp(&mut Delay).await.unwrap();
@@ -72,50 +74,167 @@ pub async fn configure_mpu(mpu: &mut Mpu9250<I2c<'static, I2C0, i2c::Async>>) {
 }
 */
 
-fn get_hid_report(
+/// Scale of the scroll speed, in hi-res units. Scrolls as fast as a detent
+/// every `PADDING_FREQ` tick did, in finer and more frequent steps.
+const SCROLL_SCALE: f32 = SCROLL_MULTIPLIER as f32 * PADDING_FREQ as f32 / READ_FREQ as f32;
+
+/// Scroll below one hi-res unit, carried over to the next reports
+#[derive(Default)]
+struct Scroll {
+    wheel: f32,
+    pan: f32,
+}
+
+impl Scroll {
+    /// Whole hi-res units to report for these deltas, keeping the rest
+    fn step(&mut self, wheel: f32, pan: f32) -> (i8, i8) {
+        let take = |remainder: &mut f32, delta: f32| {
+            let total = *remainder + delta;
+            let units = roundf(total).clamp(i8::MIN as f32, i8::MAX as f32);
+            *remainder = total - units;
+            units as i8
+        };
+        (take(&mut self.wheel, wheel), take(&mut self.pan, pan))
+    }
+}
+
+fn get_hid_report<const N: usize>(
     vel_x: f32, vel_y: f32,
-    finger_states: &[bool; 3],
+    pointer: &PointerSettings,
+    keymap: &Keymap,
+    fingers: &[Finger; N],
+    finger_states: &[bool; N],
     tap: bool,
-    last_padding: &mut Instant
+    scroll: &mut Scroll
 ) -> HidInstruction {
     // Make Hid reports from sensor processing
     let mut mouse_report = MouseReport { buttons: 0, x:0, y:0, wheel: 0, pan: 0 };
     let mut keyboard_report = KeyboardReport { modifier: 0, reserved: 0, leds: 0, keycodes: [0, 0, 0, 0, 0, 0] };
-    let media_report = MediaKeyboardReport { usage_id: MediaKey::Zero.into() };
+    let mut media_report = MediaKeyboardReport { usage_id: MediaKey::Zero.into() };
+    let mut system = 0;
+    let mut extra_keys = KeyBitmap::new();
     
     match tap {
         false => {
-            mouse_report.x = roundf(-vel_y * ROLL_SENS) as i8;
-            mouse_report.y = roundf(vel_x * PITCH_SENS) as i8;
+            mouse_report.x = roundf(-vel_y * pointer.roll_sens) as i8;
+            mouse_report.y = roundf(vel_x * pointer.pitch_sens) as i8;
         },
         true => {
-            let refresh = Duration::from_hz(PADDING_FREQ);
-            if last_padding.elapsed() >= refresh {
-                mouse_report.wheel = roundf(vel_x * WHEEL_SENS) as i8;
-                mouse_report.pan = roundf(-vel_y * PAN_SENS) as i8;
-
-                *last_padding = last_padding.saturating_add(refresh);
-            }
-            // Continue if not enough time elapsed
+            (mouse_report.wheel, mouse_report.pan) = scroll.step(
+                vel_x * pointer.wheel_sens * SCROLL_SCALE,
+                -vel_y * pointer.pan_sens * SCROLL_SCALE,
+            );
         }
     };
-    if finger_states[INDEX] {mouse_report.buttons = LEFT_CLICK};
-    if finger_states[MIDDLE] {mouse_report.buttons = RIGHT_CLICK};
-    if finger_states[RING] {keyboard_report.modifier = 0x01};  // Left control
+    let mut keycodes = keyboard_report.keycodes.iter_mut();
+    for (finger, closed) in fingers.iter().zip(finger_states) {
+        if !closed { continue; }
+        match keymap[finger.keymap_index()] {
+            FingerAction::LeftClick => mouse_report.buttons |= LEFT_CLICK,
+            FingerAction::RightClick => mouse_report.buttons |= RIGHT_CLICK,
+            FingerAction::MiddleClick => mouse_report.buttons |= MIDDLE_CLICK,
+            FingerAction::Modifier(bits) => keyboard_report.modifier |= bits,
+            FingerAction::Key(usage) => match keycodes.next() {
+                Some(keycode) => *keycode = usage,
+                None => extra_keys.press(usage),
+            },
+            // Only one of each is held, the last closed finger wins
+            FingerAction::Consumer(usage) => media_report.usage_id = usage,
+            FingerAction::System(usage) => system = usage,
+            FingerAction::None => {}
+        }
+    }
 
     HidInstruction {
         mouse: mouse_report,
         keyboard: keyboard_report,
-        media: media_report
+        media: media_report,
+        system,
+        extra_keys,
+    }
+}
+
+
+/// Angle in degrees as a gamepad axis, full scale at ±180°
+fn axis(degrees: f32) -> i16 {
+    roundf(degrees / 180.0 * i16::MAX as f32).clamp(-(i16::MAX as f32), i16::MAX as f32) as i16
+}
+
+/// Orientation, finger bend between the flex bands and a button per closed finger
+fn get_gamepad_report<const N: usize>(
+    orientation: [f32; 3],
+    flex: &FlexSettings,
+    fingers: &[Finger; N],
+    flexes: &FingerReadings<N>,
+    finger_states: &[bool; N],
+    tap: bool
+) -> GamepadReport {
+    let mut report = GamepadReport::new();
+    report.orientation = orientation.map(axis);
+    // Readings fall as the finger closes, from the upper band to the lower one
+    let span = flex.sup_band.saturating_sub(flex.low_band).max(1) as f32;
+    for ((finger, reading), closed) in fingers.iter().zip(flexes).zip(finger_states) {
+        let bend = flex.sup_band.saturating_sub(*reading) as f32 / span;
+        report.fingers[finger.keymap_index()] = roundf(bend.min(1.0) * u8::MAX as f32) as u8;
+        if *closed {
+            report.buttons |= 1 << finger.keymap_index();
+        }
+    }
+    if tap {
+        report.buttons |= GAMEPAD_TAP_BUTTON;
+    }
+    report
+}
+
+
+/// Orientation from roll, pitch and yaw in degrees, as a Q14 quaternion w, x, y, z
+fn quaternion(roll: f32, pitch: f32, yaw: f32) -> [i16; 4] {
+    let half = |degrees: f32| degrees * PI / 360.0;
+    let (sr, cr) = (sinf(half(roll)), cosf(half(roll)));
+    let (sp, cp) = (sinf(half(pitch)), cosf(half(pitch)));
+    let (sy, cy) = (sinf(half(yaw)), cosf(half(yaw)));
+    [
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    ].map(|component| roundf(component * (1 << 14) as f32) as i16)
+}
+
+/// Sample for host apps, flexes ordered from thumb to pinky
+fn get_telemetry<const N: usize>(
+    orientation: [f32; 3],
+    accel: &Accel,
+    gyro: &Gyro,
+    mag: &Mag,
+    fingers: &[Finger; N],
+    flexes: &FingerReadings<N>,
+    tap: bool
+) -> Telemetry {
+    let mut finger_readings = [0; TELEMETRY_FINGERS];
+    for (finger, reading) in fingers.iter().zip(flexes) {
+        finger_readings[finger.keymap_index()] = *reading;
+    }
+    let [roll, pitch, yaw] = orientation;
+    Telemetry {
+        timestamp_ms: Instant::now().as_millis() as u32,
+        quaternion: quaternion(roll, pitch, yaw),
+        accel: [accel.x(), accel.y(), accel.z()],
+        gyro: [gyro.x(), gyro.y(), gyro.z()],
+        mag: [mag.x(), mag.y(), mag.z()],
+        flexes: finger_readings,
+        tap,
     }
 }
 
 
-async fn read_sensors(
-    mpu: &mut Mpu9250<I2c<'static, I2C0, i2c::Async>>,
-    finger_flexes: &mut FingerFlexes<'static>,
-    finger_tap: &mut Input<'static>
-) -> (Accel, Gyro, FingerReadings, bool) {
+/// Reads every sensor, a failed flex read keeps the `last_flexes`
+async fn read_sensors<B: FlexBackend<N>, const N: usize>(
+    mpu: &mut Mpu9250<I2cBusDevice>,
+    finger_flexes: &mut FingerFlexes<B, N>,
+    finger_tap: &mut Input<'static>,
+    last_flexes: &FingerReadings<N>
+) -> (Accel, Gyro, Mag, FingerReadings<N>, bool) {
     // Tries to get accel and gyro data from motion6, in case of error returns zeros
     let (accel, gyro, mag) = match mpu.motion9().await {
         Err(e) => {
@@ -128,35 +247,40 @@ async fn read_sensors(
     };
     let flexes = match finger_flexes.read().await {
         Err(e) => {
-            log::warn!("Error {:?} while reading ADC", {e});
-            [0,0,0] // Return empty readings
+            log::warn!("Error {:?} while reading flexes", {e});
+            // Zeros would read as closed fingers and fire their actions
+            *last_flexes
         }
         Ok(readings) => readings
     };
     let tap = finger_tap.is_high();
     log::info!("Sensor Readings:");
-    log::info!("Index: {}\nMiddle: {}\nRing: {}\n", flexes[INDEX], flexes[MIDDLE], flexes[RING]);
+    for (finger, flex) in finger_flexes.fingers().iter().zip(flexes) {
+        log::info!("{:?}: {}", finger, flex);
+    }
     log::info!("Thumb and Index are touching: {}", tap);
     log::info!("Accelerometer [mg]: x={}, y={}, z={}", accel.x(), accel.y(), accel.z());
     log::info!("Gyroscope [deg/s]: x={}, y={}, z={}", gyro.x(), gyro.y(), gyro.z());
     log::info!("Magnetometer [algo]: x={}, y={}, z={}", mag.x(), mag.y(), mag.z());
 
-    (accel, gyro, flexes, tap)
+    (accel, gyro, mag, flexes, tap)
 }
 
 
 #[embassy_executor::task]
 pub async fn sensor_processing(
-    mut mpu: Mpu9250<I2c<'static, I2C0, i2c::Async>>,
-    mut finger_flexes: FingerFlexes<'static>,
+    mut mpu: Mpu9250<I2cBusDevice>,
+    mut finger_flexes: FingerFlexes<FlexInputs, FLEX_COUNT>,
     mut finger_tap: Input<'static>,
-    tx_ch: Sender<'static, CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE>
+    mut wom: WakeOnMotion,
+    tx_ch: Sender<'static, CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE>,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore
 ) -> ! {
-    // Schmitt Trigger bands
-    const SUP_BAND: u16 = 900;
-    const LOW_BAND: u16 = 650;
     // Current flexes states
-    let mut finger_states: [bool; 3] = [OPENED; 3];
+    let mut finger_states: [bool; FLEX_COUNT] = [OPENED; FLEX_COUNT];
+    // Last good flex readings, open until the first read
+    let mut flexes: [u16; FLEX_COUNT] = [u16::MAX; FLEX_COUNT];
 
     // MPU calculation constants:
     const ALPHA_ACC: f32 = 0.05;    // Relative weight of the accelerometer compared to the gyroscope
@@ -165,23 +289,94 @@ pub async fn sensor_processing(
     let mut roll: f32;
     let mut angle_x: f32 = 0.0;
     let mut angle_y: f32 = 0.0;
+    // Heading from the gyro alone, it drifts and is only used by the gamepad
+    let mut yaw: f32 = 0.0;
 
-    // Mouse padding delay:
-    let mut last_padding = Instant::now();
+    // Scroll carried between reports
+    let mut scroll = Scroll::default();
+    // Inactivity sleep
+    let mut idle_detector = IdleDetector::new();
+    // Profile switching gesture
+    let mut tap_counter = TapCounter::new();
+    // Mouse buttons of the last report, new presses are felt as a tick
+    let mut last_buttons = 0;
+    let mut last_mode = OutputMode::Pointer;
+    // Time of the last sample, the loop period grows with the sensors read
+    let mut last_sample = Instant::now();
+    let mut calibration: Option<Calibration<FLEX_COUNT>> = None;
+    // When the next telemetry is due
+    let mut next_telemetry = Instant::now();
     loop {
+        // Settings may change at runtime
+        let (pointer, flex, keymap, mode, power, telemetry_rate) = settings.lock(|settings| {
+            let settings = settings.borrow();
+            let profile = settings.profile();
+            (profile.pointer, profile.flex, profile.keymap, profile.mode, settings.power, settings.telemetry_rate)
+        });
+        // Leaving a mode releases what it held on the dongle
+        if mode != last_mode {
+            log::info!("Output mode changed to {}", mode);
+            let release = match last_mode {
+                OutputMode::Pointer => GloveMessage::Hid(HidInstruction::released()),
+                OutputMode::Gamepad => GloveMessage::Gamepad(GamepadReport::new()),
+            };
+            tx_ch.send(release).await;
+            last_mode = mode;
+            last_buttons = 0;
+        }
+
         // Read sensor data
-        let (accel, gyro, flexes, tap) = read_sensors(&mut mpu, &mut finger_flexes, &mut finger_tap).await;
+        let (accel, gyro, mag, readings, tap) = read_sensors(&mut mpu, &mut finger_flexes, &mut finger_tap, &flexes).await;
+        flexes = readings;
+        let now = Instant::now();
+        let dt = (now - last_sample).as_micros() as f32 / 1_000_000.0;
+        last_sample = now;
+
+        if tap_counter.update(tap) {
+            // Held while switching, a remote change saving meanwhile would store a stale profile
+            let mut store = store.lock().await;
+            let updated = settings.lock(|settings| {
+                let mut settings = settings.borrow_mut();
+                settings.next_profile();
+                settings.clone()
+            });
+            log::info!("Switched to profile {}", updated.profile().name);
+            haptics::play(HapticPattern::DoublePulse);
+            if let Err(e) = store.save(&updated, ConfigKey::ActiveProfile) {
+                log::warn!("Error {:?} while saving the active profile", e);
+            }
+        }
+
+        if idle_detector.is_idle(&gyro, tap, finger_states.contains(&CLOSED), &power) {
+            sleep_until_motion(&mut wom, &mut finger_tap, &tx_ch, &power).await;
+            idle_detector.reset();
+            scroll = Scroll::default();
+            last_sample = Instant::now();
+            continue;
+        }
 
         // Schmitt Trigger implemented for fingers
-        for (idx, flex) in flexes.iter().enumerate() {
+        for (idx, reading) in flexes.iter().enumerate() {
             finger_states[idx] = 
-                if *flex >= SUP_BAND { OPENED }
-                else if *flex <= LOW_BAND { CLOSED }
+                if *reading >= flex.sup_band { OPENED }
+                else if *reading <= flex.low_band { CLOSED }
                 else { finger_states[idx] };
         }
 
-        log::info!("Index [bool]: {}\nMiddle [bool]: {}\nThumb [bool]: {}\n",
-        finger_states[INDEX], finger_states[MIDDLE], finger_states[RING]);
+        // Flex band calibration, the fingers close on purpose and hold nothing meanwhile
+        if calibration.is_none() {
+            calibration = Calibration::requested();
+        }
+        if calibration.is_some() {
+            finger_states = [OPENED; FLEX_COUNT];
+        }
+        if let Some(done) = calibration.take_if(|calibration| calibration.update(&flexes)) {
+            done.finish(settings, store, &tx_ch).await;
+        }
+
+        for (finger, state) in finger_flexes.fingers().iter().zip(finger_states) {
+            log::info!("{:?} [bool]: {}", finger, state);
+        }
 
         // Process mpu
         roll = atan2f(
@@ -192,22 +387,45 @@ pub async fn sensor_processing(
             (-1*accel.x()).into(),
             sqrtf(( accel.y() as i32 * accel.y() as i32 + accel.z() as i32 * accel.z() as i32 ) as f32 )) * 180.0 / PI;
 
-        angle_x = (1.0 - ALPHA_ACC) * (angle_x + gyro.x() as f32 * DELTA_TIME) + ALPHA_ACC * roll;
-        angle_y = (1.0 - ALPHA_ACC) * (angle_y + gyro.y() as f32 * DELTA_TIME) + ALPHA_ACC * pitch;
+        angle_x = (1.0 - ALPHA_ACC) * (angle_x + gyro.x() as f32 * dt) + ALPHA_ACC * roll;
+        angle_y = (1.0 - ALPHA_ACC) * (angle_y + gyro.y() as f32 * dt) + ALPHA_ACC * pitch;
+        yaw += gyro.z() as f32 * dt;
+        if yaw.abs() > 180.0 {
+            yaw -= 360.0 * yaw.signum();
+        }
 
-        let vel_x = match angle_x.abs() > DEAD_ZONE {
+        // Host apps get telemetry_rate samples per second whatever the mode,
+        // dropped rather than delaying input while the link is busy
+        if telemetry_rate > 0 && Instant::now() >= next_telemetry {
+            let telemetry = get_telemetry([angle_x, angle_y, yaw], &accel, &gyro, &mag, finger_flexes.fingers(), &flexes, tap);
+            let _ = tx_ch.try_send(GloveMessage::Telemetry(telemetry));
+            next_telemetry = (next_telemetry + Duration::from_hz(telemetry_rate as u64)).max(Instant::now());
+        }
+
+        if mode == OutputMode::Gamepad {
+            let report = get_gamepad_report([angle_x, angle_y, yaw], &flex, finger_flexes.fingers(), &flexes, &finger_states, tap);
+            tx_ch.send(GloveMessage::Gamepad(report)).await;
+            Timer::after(Duration::from_hz(READ_FREQ)).await;
+            continue;
+        }
+
+        let vel_x = match angle_x.abs() > pointer.dead_zone {
             false => 0.0,
-            true => angle_x.signum() * DELTA_TIME * powf(angle_x.abs() - DEAD_ZONE, 1.2)
+            true => angle_x.signum() * dt * powf(angle_x.abs() - pointer.dead_zone, pointer.curve)
         };
-        let vel_y = match angle_y.abs() > DEAD_ZONE {
+        let vel_y = match angle_y.abs() > pointer.dead_zone {
             false => 0.0,
-            true => angle_y.signum() * DELTA_TIME * powf(angle_y.abs() - DEAD_ZONE, 1.2)
+            true => angle_y.signum() * dt * powf(angle_y.abs() - pointer.dead_zone, pointer.curve)
         };
         log::info!("vel_x: {}, vel_y: {}", vel_x, vel_y);
 
         // Get hid combination from sensors and send it to tcp client
-        let hid_report = get_hid_report(vel_x, vel_y, &finger_states, tap, &mut last_padding);
-        tx_ch.send(hid_report).await;
+        let hid_report = get_hid_report(vel_x, vel_y, &pointer, &keymap, finger_flexes.fingers(), &finger_states, tap, &mut scroll);
+        if hid_report.mouse.buttons & !last_buttons != 0 {
+            haptics::play(HapticPattern::Tick);
+        }
+        last_buttons = hid_report.mouse.buttons;
+        tx_ch.send(GloveMessage::Hid(hid_report)).await;
 
         // Limit working frequency
         Timer::after(Duration::from_hz(READ_FREQ)).await;
@@ -1,68 +1,169 @@
 use {defmt_rtt as _, panic_probe as _};
 
-use core::str::FromStr;
+use core::net::Ipv4Addr;
+use cortex_m::peripheral::SCB;
 use cyw43::JoinOptions;
+use embassy_futures::select::{select3, Either3};
 use embassy_rp::clocks::RoscRng;
 use embassy_net::{
     Config,
     Stack,
-    tcp::TcpSocket,
+    IpEndpoint,
+    tcp::{self, TcpSocket},
+    udp::{PacketMetadata, UdpSocket},
     StackResources,
 };
 use embassy_sync::{
     channel::Receiver,
     blocking_mutex::raw::CriticalSectionRawMutex,
 };
-use embassy_time::{Timer, Duration, with_timeout};
+use embassy_time::{Timer, Duration, Instant, with_timeout};
 use embedded_io_async::Write;
 use static_cell::StaticCell;
 
 use shared::{
     definitions::{
-        WIFI_NETWORK, WIFI_PASSWORD,
-        DONGLE_IP, GLOVE_IP,
         TCP_ENDPOINT,
         SOCKET_TIMEOUT,
         CHANNEL_SIZE,
+        DISCOVERY_PORT,
+        DISCOVERY_TIMEOUT,
+        HEARTBEAT_PERIOD,
+        LINK_TIMEOUT,
+        SLEEP_LINK_TIMEOUT,
     },
-    custom_hid::HidInstruction
+    protocol::{GloveMessage, DongleMessage, LinkMessage, PowerState, FrameDecoder, Beacon, BEACON_SIZE, Hello},
+    config::SharedSettings,
+    storage::SharedStore,
 };
 
+use crate::{remote_config, haptics, calibration::{self, Requester}};
 
-pub fn network_config(net_device: cyw43::NetDriver<'static>) -> (embassy_net::Stack<'static>, embassy_net::Runner<'static, cyw43::NetDriver<'static>>) {
-    // Configure the network
-    let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: embassy_net::Ipv4Cidr::new(embassy_net::Ipv4Address::from_str(GLOVE_IP).unwrap(), 16),
-        dns_servers: heapless::Vec::new(),
-        gateway: None,
-    });
+
+pub fn network_config(net_device: cyw43::NetDriver<'static>, glove_ip: Ipv4Addr) -> (embassy_net::Stack<'static>, embassy_net::Runner<'static, cyw43::NetDriver<'static>>) {
+    // Configure the network, an unspecified address is taken from DHCP
+    let config = if glove_ip.is_unspecified() {
+        Config::dhcpv4(Default::default())
+    } else {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: embassy_net::Ipv4Cidr::new(glove_ip, 16),
+            dns_servers: heapless::Vec::new(),
+            gateway: None,
+        })
+    };
 
     // Generate random seed
     let seed = RoscRng.next_u64();
 
-    // Init network stack
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    // Init network stack, sockets for the TCP link, UDP discovery, DHCP and DNS
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
     let (stack, runner) = embassy_net::new(net_device, config, RESOURCES.init(StackResources::new()), seed);
 
     (stack, runner)
 }
 
 
+/// Listens for the dongle beacon, giving the endpoint it accepts gloves on
+async fn discover_dongle(stack: Stack<'static>) -> Option<IpEndpoint> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 16];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(DISCOVERY_PORT) {
+        log::warn!("Discovery socket bind error: {:?}", e);
+        return None;
+    }
+
+    let mut buf = [0; BEACON_SIZE];
+    let listen = async {
+        loop {
+            match socket.recv_from(&mut buf).await {
+                Ok((len, meta)) => {
+                    // Other traffic on the port is ignored
+                    if let Some(beacon) = Beacon::from_be_bytes(&buf[..len]) {
+                        return IpEndpoint::new(meta.endpoint.addr, beacon.tcp_port);
+                    }
+                }
+                Err(e) => log::warn!("Discovery receive error: {:?}", e),
+            }
+        }
+    };
+    with_timeout(DISCOVERY_TIMEOUT, listen).await.ok()
+}
+
+
+/// Answers every complete request from the dongle, pings with the last sampled signal strength
+async fn handle_requests(
+    decoder: &mut FrameDecoder,
+    socket: &mut TcpSocket<'_>,
+    settings: &'static SharedSettings,
+    store: &'static SharedStore,
+    rssi: i8
+) -> Result<(), tcp::Error> {
+    while let Some(request) = decoder.next_message::<DongleMessage>() {
+        let reply = match request {
+            Err(e) => {
+                log::warn!("Dropped frame: {:?}", e);
+                continue;
+            }
+            Ok(DongleMessage::Ping { seq }) => GloveMessage::Pong { seq, rssi },
+            Ok(DongleMessage::ConfigGet { seq, key }) => GloveMessage::ConfigReply(remote_config::get(seq, key, settings)),
+            Ok(DongleMessage::ConfigSet { seq, key, value }) => {
+                GloveMessage::ConfigReply(remote_config::set(seq, key, value, settings, store).await)
+            }
+            Ok(DongleMessage::Profile { seq, action, name }) => {
+                GloveMessage::ConfigReply(remote_config::profile(seq, action, &name, settings, store).await)
+            }
+            Ok(DongleMessage::Haptic(pattern)) => {
+                haptics::play(pattern);
+                continue;
+            }
+            Ok(DongleMessage::KeyboardLeds(leds)) => {
+                haptics::show_keyboard_leds(leds);
+                continue;
+            }
+            // Answered by the sensor task once the hand has been measured
+            Ok(DongleMessage::Calibrate { seq }) => {
+                calibration::start(Requester::Dongle(seq));
+                continue;
+            }
+            Ok(DongleMessage::Reboot) => {
+                log::info!("Rebooting on dongle request");
+                socket.flush().await?;
+                SCB::sys_reset();
+            }
+        };
+        socket.write_all(&reply.to_frame()).await?;
+    }
+    Ok(())
+}
+
+
 #[embassy_executor::task]
 pub async fn tcp_client_task(
-mut control: cyw43::Control<'static>, stack: Stack<'static>, rx_ch: Receiver<'static, CriticalSectionRawMutex, HidInstruction, CHANNEL_SIZE>
+mut control: cyw43::Control<'static>, stack: Stack<'static>, rx_ch: Receiver<'static, CriticalSectionRawMutex, GloveMessage, CHANNEL_SIZE>,
+settings: &'static SharedSettings, store: &'static SharedStore
 ) -> ! {
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
-    let mut hid_instruction: HidInstruction;
+    let mut decoder = FrameDecoder::new();
+
+    // Identifies the glove to the dongle, which may serve several
+    let mac = control.address().await;
+    let device_id = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+
+    // Last power state told to the dongle, kept across reconnections
+    let mut power = PowerState::Active;
 
     // Try wifi connection
     loop {
+        let network = settings.lock(|settings| settings.borrow().network.clone());
         log::info!("Connecting to WiFi...");
         control.leave().await; // Drops any wifi association to avoid control.join(...) crashes
         // with_timeout to retry avoiding softlocks
         match with_timeout(Duration::from_secs(5), 
-        control.join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))).await {
+        control.join(&network.wifi_network, JoinOptions::new(network.wifi_password.as_bytes()))).await {
             Err(_err) => {
                 log::info!("Wifi connection failed, connection timed out");
                 Timer::after_millis(250).await;
@@ -92,29 +193,115 @@ mut control: cyw43::Control<'static>, stack: Stack<'static>, rx_ch: Receiver<'st
         tx_buffer.fill(0);
 
         loop {
+            control.gpio_set(0, false).await; // LED off
+            // A lost dongle is looked for again, only a lost network means joining again
+            if !stack.is_link_up() || !stack.is_config_up() {
+                log::warn!("WiFi link lost");
+                break;
+            }
+
+            // The dongle address is either configured or announced by its beacon
+            let dongle = if network.dongle_ip.is_unspecified() {
+                log::info!("Looking for the dongle...");
+                match discover_dongle(stack).await {
+                    Some(dongle) => dongle,
+                    None => {
+                        log::warn!("No dongle beacon heard");
+                        continue;
+                    }
+                }
+            } else {
+                IpEndpoint::new(network.dongle_ip.into(), TCP_ENDPOINT)
+            };
+
             let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
             socket.set_timeout(Some(SOCKET_TIMEOUT));
 
-            control.gpio_set(0, false).await; // LED off
-            log::info!("Connecting to TCP...");
-            let host_addr = embassy_net::Ipv4Address::from_str(DONGLE_IP).unwrap();
-            if let Err(e) = socket.connect((host_addr, TCP_ENDPOINT)).await {
+            log::info!("Connecting to TCP {}...", dongle);
+            if let Err(e) = socket.connect(dongle).await {
                 log::warn!("TCP connection error: {:?}", e);
-                break;
+                Timer::after_millis(250).await;
+                continue;
             }
 
             log::info!("Connected to {:?}", socket.remote_endpoint());
             control.gpio_set(0, true).await; // LED on
+            decoder.clear();
+
+            let hand = settings.lock(|settings| settings.borrow().link.hand);
+            let hello = GloveMessage::Hello(Hello { device_id, hand });
+            if let Err(e) = socket.write_all(&hello.to_frame()).await {
+                log::warn!("Write error: {:?}", e);
+                continue;
+            }
+            // A new connection starts active on the dongle, a sleeping glove answers slower
+            let told = match power {
+                PowerState::Active => Ok(()),
+                PowerState::Sleep => socket.write_all(&GloveMessage::Power(power).to_frame()).await,
+            };
+            if let Err(e) = told {
+                log::warn!("Write error: {:?}", e);
+                continue;
+            }
 
-            // Communication loop
+            // The dongle pings every heartbeat, silence means it is gone
+            let mut next_check = Instant::now() + HEARTBEAT_PERIOD;
+            let mut last_rx = Instant::now();
+            let mut rssi = i8::MIN;
+
+            // Communication loop, sends glove messages and answers dongle requests
             loop {
-                hid_instruction = rx_ch.receive().await;
-                let tcp_message = hid_instruction.to_be_bytes();
+                // Checked on every pass, the sensor task streaming keeps messages ready to send
+                if Instant::now() >= next_check {
+                    let timeout = match power {
+                        PowerState::Active => LINK_TIMEOUT,
+                        PowerState::Sleep => SLEEP_LINK_TIMEOUT,
+                    };
+                    if last_rx.elapsed() > timeout {
+                        log::warn!("Dongle silent for {} ms, reconnecting", last_rx.elapsed().as_millis());
+                        break;
+                    }
+                    rssi = control.get_rssi().await.clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+                    next_check = Instant::now() + HEARTBEAT_PERIOD;
+                }
+
+                let message = match select3(rx_ch.receive(), socket.read(decoder.unfilled()), Timer::at(next_check)).await {
+                    Either3::First(message) => message,
+                    Either3::Third(()) => continue,
+                    Either3::Second(Err(e)) => {
+                        log::warn!("Read error: {:?}", e);
+                        break;
+                    }
+                    Either3::Second(Ok(0)) => {
+                        log::warn!("Read EOF");
+                        break;
+                    }
+                    Either3::Second(Ok(count)) => {
+                        last_rx = Instant::now();
+                        decoder.filled(count);
+                        if let Err(e) = handle_requests(&mut decoder, &mut socket, settings, store, rssi).await {
+                            log::warn!("Write error: {:?}", e);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let tcp_message = message.to_frame();
                 if let Err(e) = socket.write_all(&tcp_message).await {
                     log::warn!("Write error: {:?}", e);
                     break;
                 }
-                log::info!("sent: {:?}", hid_instruction);
+                log::info!("sent: {:?}", message);
+
+                // Dongle already knows, lower wifi power while sleeping
+                if let GloveMessage::Power(state) = message {
+                    power = state;
+                    let mode = match state {
+                        PowerState::Active => cyw43::PowerManagementMode::None,
+                        PowerState::Sleep => cyw43::PowerManagementMode::PowerSave,
+                    };
+                    control.set_power_management(mode).await;
+                }
             }
         }
     }
@@ -0,0 +1,65 @@
+use embassy_rp::{
+    peripherals::USB,
+    usb::Driver,
+};
+use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, State as CdcState},
+    UsbDevice
+};
+use embassy_usb_logger::MAX_PACKET_SIZE;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+use shared::cli::CLI_PACKET_SIZE;
+
+use crate::cli::CliDevice;
+
+// USB Descriptors
+static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static MSOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+
+pub type LoggerDevice = CdcAcmClass<'static, Driver<'static, USB>>;
+
+/// Two serial ports while the glove is plugged in: the log and a command line
+pub fn config_usb(driver: Driver<'static, USB>) -> (UsbDevice<'static, Driver<'static, USB>>, LoggerDevice, CliDevice) {
+    // Create embassy-usb Config
+    let mut config  = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("LosDos");
+    config.product = Some("MotionGlove Glove");
+    config.serial_number = Some("11111111");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Init static memory
+    let config_descriptor = CONFIG_DESCRIPTOR.init([0; 256]);
+    let bos_descriptor = BOS_DESCRIPTOR.init([0; 256]);
+    let msos_descriptor = MSOS_DESCRIPTOR.init([0; 256]);
+    let control_buf = CONTROL_BUF.init([0; 64]);
+
+    // USB Builder
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        config,
+        config_descriptor,
+        bos_descriptor,
+        msos_descriptor,
+        control_buf,
+    );
+
+    // USB Logger config
+    static USB_LOGGER: StaticCell<CdcState<'static>> = StaticCell::new();
+    let logger_state = USB_LOGGER.init(CdcState::new());
+    let logger = CdcAcmClass::new(&mut builder, logger_state, MAX_PACKET_SIZE.into());
+
+    // Command line config, a second serial port next to the logger
+    static CLI_STATE: StaticCell<CdcState<'static>> = StaticCell::new();
+    let cli_state = CLI_STATE.init(CdcState::new());
+    let cli = CdcAcmClass::new(&mut builder, cli_state, CLI_PACKET_SIZE);
+
+    // USB Build
+    let usb = builder.build();
+
+    (usb, logger, cli)
+}
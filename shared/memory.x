@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 64K
+    /* Settings store, see CONFIG_FLASH_OFFSET in shared/src/definitions.rs */
+    CONFIG : ORIGIN = 0x10000000 + 2048K - 64K, LENGTH = 64K
 
     /* Pick one of the two options for RAM layout     */
 
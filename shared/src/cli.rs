@@ -0,0 +1,341 @@
+use core::fmt::{self, Write};
+
+use cortex_m::peripheral::SCB;
+use embassy_time::Timer;
+use embassy_usb::{
+    class::cdc_acm::CdcAcmClass,
+    driver::{Driver, EndpointError},
+};
+use heapless::String;
+
+use crate::{config::{ConfigKey, ConfigValue, Hand}, protocol::{ProfileAction, HapticPattern}};
+
+pub const CLI_PACKET_SIZE: u16  = 64;
+pub const MAX_LINE_SIZE: usize  = 96;
+pub const RESPONSE_SIZE: usize  = 1024;
+pub const PROMPT: &str          = "> ";
+pub const TRUNCATED: &str       = "... output truncated\r\n";
+const HIDDEN_VALUE: &str        = "********";
+pub const HELP: &str = "\
+status                              link, battery and uptime\r\n\
+list [<glove>]                      every setting and its value\r\n\
+get [<glove>] <key>                 read a setting\r\n\
+set [<glove>] <key> <value>         change and persist a setting\r\n\
+calibrate [<glove>]                 measure the flex bands while the hand opens and closes\r\n\
+profile [<glove>] list              glove profiles, the active one marked\r\n\
+profile [<glove>] load|save|delete <name>\r\n\
+                                    switch to, store the active as, or drop a profile\r\n\
+buzz [<glove>] tick|double|long     vibrate the glove\r\n\
+pair                                accept a new glove for a while\r\n\
+reboot [<glove>]                    restart the board\r\n\
+help                                this message\r\n\
+<glove> is glove, left or right, the first connected glove or the one on that hand\r\n";
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8    = 0x7F;
+
+pub type Line = String<MAX_LINE_SIZE>;
+
+/// Board a command applies to, the dongle unless a glove is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Dongle,
+    /// The glove on the given hand, or the first connected one
+    Glove(Option<Hand>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+    Help,
+    Status,
+    List(Target),
+    Get(Target, ConfigKey),
+    /// Value is left as text, its type depends on the key
+    Set(Target, ConfigKey, &'a str),
+    /// Flex band measurement, run locally when run on a glove
+    Calibrate(Option<Hand>),
+    /// Glove profile command, the name is empty when listing
+    Profile(Option<Hand>, ProfileAction, &'a str),
+    /// Glove vibration, played locally when run on a glove
+    Buzz(Option<Hand>, HapticPattern),
+    Pair,
+    Reboot(Target),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CliError {
+    UnknownCommand,
+    MissingArgument,
+    UnknownKey,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand => write!(f, "unknown command, try help"),
+            CliError::MissingArgument => write!(f, "missing argument, try help"),
+            CliError::UnknownKey => write!(f, "unknown key, try list"),
+        }
+    }
+}
+
+/// Splits off the first word of `text`
+fn next_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(' ') {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Consumes an optional `glove`, `left` or `right` target word
+fn target(args: &str) -> (Target, &str) {
+    match next_word(args) {
+        ("glove", rest) => (Target::Glove(None), rest),
+        ("left", rest) => (Target::Glove(Some(Hand::Left)), rest),
+        ("right", rest) => (Target::Glove(Some(Hand::Right)), rest),
+        _ => (Target::Dongle, args),
+    }
+}
+
+/// Target of commands that only apply to gloves, any target word picks one
+fn glove(args: &str) -> (Option<Hand>, &str) {
+    match target(args) {
+        (Target::Glove(hand), rest) => (hand, rest),
+        (Target::Dongle, rest) => (None, rest),
+    }
+}
+
+fn key(args: &str) -> Result<(ConfigKey, &str), CliError> {
+    match next_word(args) {
+        ("", _) => Err(CliError::MissingArgument),
+        (name, rest) => ConfigKey::from_name(name).map(|key| (key, rest)).ok_or(CliError::UnknownKey),
+    }
+}
+
+/// Parses a command line, None for a blank line
+pub fn parse(line: &str) -> Result<Option<Command<'_>>, CliError> {
+    let (name, args) = next_word(line.trim());
+    let command = match name {
+        "" => return Ok(None),
+        "help" => Command::Help,
+        "status" => Command::Status,
+        "list" => Command::List(target(args).0),
+        "get" => {
+            let (target, args) = target(args);
+            Command::Get(target, key(args)?.0)
+        }
+        "set" => {
+            let (target, args) = target(args);
+            let (key, value) = key(args)?;
+            if value.is_empty() {
+                return Err(CliError::MissingArgument);
+            }
+            // Values such as network names may hold spaces
+            Command::Set(target, key, value.trim_end())
+        }
+        "calibrate" => Command::Calibrate(glove(args).0),
+        "profile" => {
+            let (hand, args) = glove(args);
+            let (action, name) = next_word(args);
+            let action = match action {
+                "list" => return Ok(Some(Command::Profile(hand, ProfileAction::List, ""))),
+                "load" => ProfileAction::Load,
+                "save" => ProfileAction::Save,
+                "delete" => ProfileAction::Delete,
+                "" => return Err(CliError::MissingArgument),
+                _ => return Err(CliError::UnknownCommand),
+            };
+            match next_word(name) {
+                ("", _) => return Err(CliError::MissingArgument),
+                (name, _) => Command::Profile(hand, action, name),
+            }
+        }
+        "buzz" => {
+            let (hand, args) = glove(args);
+            let pattern = match next_word(args).0 {
+                "tick" => HapticPattern::Tick,
+                "double" => HapticPattern::DoublePulse,
+                "long" => HapticPattern::LongBuzz,
+                "" => return Err(CliError::MissingArgument),
+                _ => return Err(CliError::UnknownCommand),
+            };
+            Command::Buzz(hand, pattern)
+        }
+        "pair" => Command::Pair,
+        "reboot" => Command::Reboot(target(args).0),
+        _ => return Err(CliError::UnknownCommand),
+    };
+    Ok(Some(command))
+}
+
+
+/// What the terminal should show after feeding a byte to the `LineEditor`
+#[derive(Debug, PartialEq)]
+pub enum Edit {
+    /// Nothing to show
+    None,
+    /// Byte accepted, echo it back
+    Echo(u8),
+    /// Last byte removed, erase it from the terminal
+    Erase,
+    /// Line finished
+    Line(Line),
+}
+
+/// Minimal line editing for serial terminals, which don't echo on their own
+pub struct LineEditor {
+    line: Line,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor { line: String::new() }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Edit {
+        match byte {
+            b'\r' | b'\n' => {
+                // A CR LF pair would otherwise end a second, empty line
+                if byte == b'\n' && self.line.is_empty() {
+                    return Edit::None;
+                }
+                Edit::Line(core::mem::take(&mut self.line))
+            }
+            BACKSPACE | DELETE => match self.line.pop() {
+                Some(_) => Edit::Erase,
+                None => Edit::None,
+            },
+            // Printable ASCII only, extra bytes past the line size are dropped
+            b' '..=b'~' => match self.line.push(byte as char) {
+                Ok(()) => Edit::Echo(byte),
+                Err(()) => Edit::None,
+            },
+            _ => Edit::None,
+        }
+    }
+}
+
+
+/// Reply to a command, output past `RESPONSE_SIZE` is dropped and reported
+#[derive(Default)]
+pub struct Response {
+    pub text: String<RESPONSE_SIZE>,
+    pub truncated: bool,
+}
+
+impl Response {
+    pub const fn new() -> Self {
+        Response { text: String::new(), truncated: false }
+    }
+}
+
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Nothing after the first dropped piece, the output would have holes
+        if self.truncated || self.text.push_str(s).is_err() {
+            self.truncated = true;
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+
+/// Passwords are write only
+pub fn shown_value(key: ConfigKey, value: &ConfigValue, out: &mut Response) {
+    let _ = match key {
+        ConfigKey::WifiPassword => write!(out, "{}\r\n", HIDDEN_VALUE),
+        _ => write!(out, "{}\r\n", value),
+    };
+}
+
+
+/// Writes `data` in packets, ending the transfer with a short packet
+pub async fn write_all<'d, D: Driver<'d>>(class: &mut CdcAcmClass<'d, D>, data: &[u8]) -> Result<(), EndpointError> {
+    let max_packet = class.max_packet_size() as usize;
+    for chunk in data.chunks(max_packet) {
+        class.write_packet(chunk).await?;
+    }
+    if !data.is_empty() && data.len() % max_packet == 0 {
+        class.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+/// What the prompt does once a reply is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum After {
+    Prompt,
+    /// Restart the board, the reply reaches the host first
+    Reboot,
+}
+
+/// Runs the prompt until the terminal goes away
+async fn serve<'d, D: Driver<'d>>(
+    class: &mut CdcAcmClass<'d, D>,
+    execute: &mut impl AsyncFnMut(Command<'_>, &mut Response) -> After
+) -> Result<(), EndpointError> {
+    let mut editor = LineEditor::new();
+    let mut packet = [0; CLI_PACKET_SIZE as usize];
+    write_all(class, PROMPT.as_bytes()).await?;
+
+    loop {
+        let count = class.read_packet(&mut packet).await?;
+        for &byte in &packet[..count] {
+            let line = match editor.push(byte) {
+                Edit::None => continue,
+                Edit::Echo(byte) => {
+                    write_all(class, &[byte]).await?;
+                    continue;
+                }
+                Edit::Erase => {
+                    write_all(class, b"\x08 \x08").await?;
+                    continue;
+                }
+                Edit::Line(line) => line,
+            };
+            write_all(class, b"\r\n").await?;
+
+            let mut out = Response::new();
+            let after = match parse(&line) {
+                Err(e) => {
+                    let _ = write!(out, "error: {}\r\n", e);
+                    After::Prompt
+                }
+                Ok(None) => After::Prompt,
+                Ok(Some(command)) => execute(command, &mut out).await,
+            };
+            write_all(class, out.text.as_bytes()).await?;
+            if out.truncated {
+                write_all(class, TRUNCATED.as_bytes()).await?;
+            }
+            if after == After::Reboot {
+                // Lets the host read the reply before the port disappears
+                Timer::after_millis(100).await;
+                SCB::sys_reset();
+            }
+            write_all(class, PROMPT.as_bytes()).await?;
+        }
+    }
+}
+
+/// Serves the command line on every terminal that connects, `execute` runs each command
+pub async fn run<'d, D: Driver<'d>>(
+    mut class: CdcAcmClass<'d, D>,
+    mut execute: impl AsyncFnMut(Command<'_>, &mut Response) -> After
+) -> ! {
+    loop {
+        class.wait_connection().await;
+        log::info!("CLI terminal connected");
+        if let Err(e) = serve(&mut class, &mut execute).await {
+            log::info!("CLI terminal disconnected: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,913 @@
+use core::{cell::RefCell, fmt, net::Ipv4Addr, ops::RangeInclusive, str::FromStr};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use heapless::{String, Vec};
+
+use crate::{
+    definitions::{
+        ROLL_SENS, PITCH_SENS, WHEEL_SENS, PAN_SENS, DEAD_ZONE, POINTER_CURVE,
+        FLEX_SUP_BAND, FLEX_LOW_BAND,
+        IDLE_TIMEOUT, IDLE_GYRO_THRESHOLD, WOM_THRESHOLD_MG,
+        WIFI_NETWORK, WIFI_PASSWORD, DONGLE_IP, GLOVE_IP,
+        MIDI_CHANNEL, MIDI_CC, MIDI_NOTE,
+        TELEMETRY_RATE, READ_FREQ,
+        MAX_PROFILES, DEFAULT_PROFILE, MAX_GLOVES,
+    },
+    custom_hid::{
+        CONSUMER_NEXT_TRACK, CONSUMER_PREV_TRACK, CONSUMER_PLAY_PAUSE,
+        CONSUMER_MUTE, CONSUMER_VOLUME_UP, CONSUMER_VOLUME_DOWN,
+        SYSTEM_POWER_DOWN, SYSTEM_SLEEP, SYSTEM_WAKE_UP,
+        GAMEPAD_FINGERS,
+    },
+};
+
+pub const WIFI_NETWORK_LEN: usize   = 32;
+pub const WIFI_PASSWORD_LEN: usize  = 64;
+pub const PROFILE_NAME_LEN: usize   = 12;
+/// Fingers covered by a keymap, thumb to pinky
+pub const KEYMAP_SIZE: usize        = 5;
+/// Highest first finger CC, the MIDI mapping sends one per gamepad finger
+/// and the last stays below the channel mode messages at 120
+const MAX_MIDI_CC: u8               = 119 - (GAMEPAD_FINGERS as u8 - 1);
+// Values the glove math works with
+const SENS_RANGE: RangeInclusive<f32>               = 0.1..=1000.0;
+const DEAD_ZONE_RANGE: RangeInclusive<f32>          = 0.1..=45.0;
+const CURVE_RANGE: RangeInclusive<f32>              = 0.5..=4.0;
+const GYRO_THRESHOLD_RANGE: RangeInclusive<f32>     = 0.1..=250.0;
+const IDLE_TIMEOUT_RANGE: RangeInclusive<u16>       = 10..=3600;
+const WOM_THRESHOLD_RANGE: RangeInclusive<u16>      = 4..=1020;
+/// Longest encoded finger action, a kind and a consumer usage
+const ACTION_SIZE: usize            = 3;
+/// Largest encoded value: type tag plus the longest text
+pub const MAX_VALUE_SIZE: usize     = 1 + WIFI_PASSWORD_LEN;
+/// Text shown and accepted for the unspecified address, taken from DHCP
+pub const AUTO_IP: &str             = "auto";
+
+// Value type tags, stored in flash
+const TYPE_FLOAT: u8    = 0x01;
+const TYPE_INT: u8      = 0x02;
+const TYPE_TEXT: u8     = 0x03;
+const TYPE_IP: u8       = 0x04;
+const TYPE_ACTION: u8   = 0x05;
+const TYPE_HAND: u8     = 0x06;
+const TYPE_MODE: u8     = 0x07;
+
+// HID keyboard modifier bits
+const MOD_CTRL: u8      = 0x01;
+const MOD_SHIFT: u8     = 0x02;
+const MOD_ALT: u8       = 0x04;
+const MOD_GUI: u8       = 0x08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    WrongType,
+    OutOfRange,
+    UnknownProfile,
+    /// Every profile slot is taken
+    ProfilesFull,
+    /// The active profile can't be deleted
+    ProfileInUse,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::WrongType => write!(f, "wrong value type"),
+            ConfigError::OutOfRange => write!(f, "value out of range"),
+            ConfigError::UnknownProfile => write!(f, "no profile with that name"),
+            ConfigError::ProfilesFull => write!(f, "no free profile slot"),
+            ConfigError::ProfileInUse => write!(f, "the profile is active"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerSettings {
+    pub roll_sens: f32,
+    pub pitch_sens: f32,
+    pub wheel_sens: f32,
+    pub pan_sens: f32,
+    pub dead_zone: f32,
+    /// Exponent applied to the angle past the dead zone
+    pub curve: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlexSettings {
+    /// Schmitt trigger bands, a finger opens above `sup_band` and closes below `low_band`
+    pub sup_band: u16,
+    pub low_band: u16,
+}
+
+/// What a closed finger does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerAction {
+    None,
+    LeftClick,
+    RightClick,
+    MiddleClick,
+    /// HID modifier bits, such as 0x01 for left control
+    Modifier(u8),
+    /// HID keyboard usage id
+    Key(u8),
+    /// Consumer page usage, such as 0xE9 for volume up, held while the finger is closed
+    Consumer(u16),
+    /// System Control usage: 0x81 power down, 0x82 sleep, 0x83 wake up
+    System(u8),
+}
+
+/// Finger actions indexed from thumb to pinky
+pub type Keymap = [FingerAction; KEYMAP_SIZE];
+
+pub const DEFAULT_KEYMAP: Keymap = [
+    FingerAction::None,                 // Thumb
+    FingerAction::LeftClick,            // Index
+    FingerAction::RightClick,           // Middle
+    FingerAction::Modifier(MOD_CTRL),   // Ring
+    FingerAction::None,                 // Pinky
+];
+
+/// Settings that change with whoever wears the glove
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// Empty for an unused slot
+    pub name: String<PROFILE_NAME_LEN>,
+    pub pointer: PointerSettings,
+    pub flex: FlexSettings,
+    pub keymap: Keymap,
+    pub mode: OutputMode,
+}
+
+/// What the glove sends the dongle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Mouse and keyboard reports, moving the pointer
+    Pointer,
+    /// Orientation, finger bend and buttons for the dongle gamepad
+    Gamepad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerSettings {
+    pub idle_timeout_s: u16,
+    pub idle_gyro_threshold: f32,
+    pub wom_threshold_mg: u16,
+}
+
+/// Hand a glove is worn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+/// How gloves present themselves and share a dongle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkSettings {
+    /// Hand of this glove, announced to the dongle
+    pub hand: Hand,
+    /// Hand whose glove moves the pointer when several are connected, read by the dongle
+    pub pointer_hand: Hand,
+    /// Device ids of the gloves the dongle accepts, 0 for a free entry.
+    /// Any glove is accepted until one is paired.
+    pub paired: [u32; MAX_GLOVES],
+}
+
+/// How the dongle maps the gamepad stream to MIDI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidiSettings {
+    /// 1 to 16
+    pub channel: u8,
+    /// Control change of the thumb bend, the other fingers take the following numbers
+    pub cc: u8,
+    /// Note held while the tap is closed
+    pub note: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkSettings {
+    pub wifi_network: String<WIFI_NETWORK_LEN>,
+    pub wifi_password: String<WIFI_PASSWORD_LEN>,
+    pub dongle_ip: Ipv4Addr,
+    pub glove_ip: Ipv4Addr,
+}
+
+/// Every tunable of both boards, defaulting to the compiled constants
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub profiles: [Profile; MAX_PROFILES],
+    /// Slot of the profile in use
+    pub active_profile: usize,
+    pub power: PowerSettings,
+    pub link: LinkSettings,
+    pub midi: MidiSettings,
+    /// Telemetry samples per second the glove streams to host apps, 0 disables it
+    pub telemetry_rate: u16,
+    pub network: NetworkSettings,
+}
+
+/// Settings read by the running tasks and updated at runtime
+pub type SharedSettings = Mutex<CriticalSectionRawMutex, RefCell<Settings>>;
+
+impl Default for Profile {
+    /// Unused slot holding the compiled constants
+    fn default() -> Self {
+        Profile {
+            name: String::new(),
+            pointer: PointerSettings {
+                roll_sens:  ROLL_SENS,
+                pitch_sens: PITCH_SENS,
+                wheel_sens: WHEEL_SENS,
+                pan_sens:   PAN_SENS,
+                dead_zone:  DEAD_ZONE,
+                curve:      POINTER_CURVE,
+            },
+            flex: FlexSettings {
+                sup_band: FLEX_SUP_BAND,
+                low_band: FLEX_LOW_BAND,
+            },
+            keymap: DEFAULT_KEYMAP,
+            mode: OutputMode::Pointer,
+        }
+    }
+}
+
+impl Profile {
+    pub fn is_used(&self) -> bool {
+        !self.name.is_empty()
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let mut profiles: [Profile; MAX_PROFILES] = Default::default();
+        profiles[0].name = String::from_str(DEFAULT_PROFILE).unwrap();
+        Settings {
+            profiles,
+            active_profile: 0,
+            power: PowerSettings {
+                idle_timeout_s:         IDLE_TIMEOUT.as_secs() as u16,
+                idle_gyro_threshold:    IDLE_GYRO_THRESHOLD,
+                wom_threshold_mg:       WOM_THRESHOLD_MG,
+            },
+            link: LinkSettings {
+                hand:           Hand::Right,
+                pointer_hand:   Hand::Right,
+                paired:         [0; MAX_GLOVES],
+            },
+            midi: MidiSettings {
+                channel:        MIDI_CHANNEL,
+                cc:             MIDI_CC,
+                note:           MIDI_NOTE,
+            },
+            telemetry_rate: TELEMETRY_RATE,
+            network: NetworkSettings {
+                wifi_network:   String::from_str(WIFI_NETWORK).unwrap(),
+                wifi_password:  String::from_str(WIFI_PASSWORD).unwrap(),
+                dongle_ip:      Ipv4Addr::from_str(DONGLE_IP).unwrap(),
+                glove_ip:       Ipv4Addr::from_str(GLOVE_IP).unwrap(),
+            },
+        }
+    }
+}
+
+impl Settings {
+    /// Profile in use
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active_profile]
+    }
+
+    /// Reads a setting, profile fields come from the active profile
+    pub fn get(&self, key: ConfigKey) -> ConfigValue {
+        self.get_in(self.active_profile, key)
+    }
+
+    /// Like `get`, reading profile fields from the profile in `slot`
+    pub fn get_in(&self, slot: usize, key: ConfigKey) -> ConfigValue {
+        let profile = &self.profiles[slot];
+        match key {
+            ConfigKey::RollSens             => ConfigValue::Float(profile.pointer.roll_sens),
+            ConfigKey::PitchSens            => ConfigValue::Float(profile.pointer.pitch_sens),
+            ConfigKey::WheelSens            => ConfigValue::Float(profile.pointer.wheel_sens),
+            ConfigKey::PanSens              => ConfigValue::Float(profile.pointer.pan_sens),
+            ConfigKey::DeadZone             => ConfigValue::Float(profile.pointer.dead_zone),
+            ConfigKey::PointerCurve         => ConfigValue::Float(profile.pointer.curve),
+            ConfigKey::OutputMode           => ConfigValue::Mode(profile.mode),
+            ConfigKey::FlexSupBand          => ConfigValue::Int(profile.flex.sup_band as u32),
+            ConfigKey::FlexLowBand          => ConfigValue::Int(profile.flex.low_band as u32),
+            ConfigKey::ThumbAction          => ConfigValue::Action(profile.keymap[0]),
+            ConfigKey::IndexAction          => ConfigValue::Action(profile.keymap[1]),
+            ConfigKey::MiddleAction         => ConfigValue::Action(profile.keymap[2]),
+            ConfigKey::RingAction           => ConfigValue::Action(profile.keymap[3]),
+            ConfigKey::PinkyAction          => ConfigValue::Action(profile.keymap[4]),
+            ConfigKey::ProfileName          => ConfigValue::Text(text_setting(&profile.name).unwrap()),
+            ConfigKey::IdleTimeout          => ConfigValue::Int(self.power.idle_timeout_s as u32),
+            ConfigKey::IdleGyroThreshold    => ConfigValue::Float(self.power.idle_gyro_threshold),
+            ConfigKey::WomThreshold         => ConfigValue::Int(self.power.wom_threshold_mg as u32),
+            ConfigKey::TelemetryRate        => ConfigValue::Int(self.telemetry_rate as u32),
+            ConfigKey::Hand                 => ConfigValue::Hand(self.link.hand),
+            ConfigKey::PointerHand          => ConfigValue::Hand(self.link.pointer_hand),
+            ConfigKey::PairedGlove1         => ConfigValue::Int(self.link.paired[0]),
+            ConfigKey::PairedGlove2         => ConfigValue::Int(self.link.paired[1]),
+            ConfigKey::MidiChannel          => ConfigValue::Int(self.midi.channel as u32),
+            ConfigKey::MidiCc               => ConfigValue::Int(self.midi.cc as u32),
+            ConfigKey::MidiNote             => ConfigValue::Int(self.midi.note as u32),
+            ConfigKey::WifiNetwork          => ConfigValue::Text(text_setting(&self.network.wifi_network).unwrap()),
+            ConfigKey::WifiPassword         => ConfigValue::Text(self.network.wifi_password.clone()),
+            ConfigKey::DongleIp             => ConfigValue::Ip(self.network.dongle_ip),
+            ConfigKey::GloveIp              => ConfigValue::Ip(self.network.glove_ip),
+            ConfigKey::ActiveProfile        => ConfigValue::Int(self.active_profile as u32),
+        }
+    }
+
+    /// Updates a single setting, checking its type and range.
+    /// Profile fields are changed in the active profile.
+    pub fn set(&mut self, key: ConfigKey, value: ConfigValue) -> Result<(), ConfigError> {
+        // The trigger needs the lower band below the upper one
+        let flex = self.profile().flex;
+        let crossed = match key {
+            ConfigKey::FlexSupBand => value.as_u16()? <= flex.low_band,
+            ConfigKey::FlexLowBand => value.as_u16()? >= flex.sup_band,
+            _ => false,
+        };
+        if crossed {
+            return Err(ConfigError::OutOfRange);
+        }
+        self.set_in(self.active_profile, key, value)
+    }
+
+    /// Like `set`, changing profile fields in the profile in `slot`.
+    /// The flex bands aren't checked against each other, stored ones load one at a time.
+    pub fn set_in(&mut self, slot: usize, key: ConfigKey, value: ConfigValue) -> Result<(), ConfigError> {
+        if key == ConfigKey::ActiveProfile {
+            let active = value.as_u16()? as usize;
+            if !self.profiles.get(active).is_some_and(Profile::is_used) {
+                return Err(ConfigError::UnknownProfile);
+            }
+            self.active_profile = active;
+            return Ok(());
+        }
+        let profile = self.profiles.get_mut(slot).ok_or(ConfigError::OutOfRange)?;
+        match key {
+            ConfigKey::RollSens             => profile.pointer.roll_sens = value.as_f32_in(SENS_RANGE)?,
+            ConfigKey::PitchSens            => profile.pointer.pitch_sens = value.as_f32_in(SENS_RANGE)?,
+            ConfigKey::WheelSens            => profile.pointer.wheel_sens = value.as_f32_in(SENS_RANGE)?,
+            ConfigKey::PanSens              => profile.pointer.pan_sens = value.as_f32_in(SENS_RANGE)?,
+            ConfigKey::DeadZone             => profile.pointer.dead_zone = value.as_f32_in(DEAD_ZONE_RANGE)?,
+            ConfigKey::PointerCurve         => profile.pointer.curve = value.as_f32_in(CURVE_RANGE)?,
+            ConfigKey::OutputMode           => profile.mode = value.as_mode()?,
+            ConfigKey::FlexSupBand          => profile.flex.sup_band = value.as_u16()?,
+            ConfigKey::FlexLowBand          => profile.flex.low_band = value.as_u16()?,
+            ConfigKey::ThumbAction          => profile.keymap[0] = value.as_action()?,
+            ConfigKey::IndexAction          => profile.keymap[1] = value.as_action()?,
+            ConfigKey::MiddleAction         => profile.keymap[2] = value.as_action()?,
+            ConfigKey::RingAction           => profile.keymap[3] = value.as_action()?,
+            ConfigKey::PinkyAction          => profile.keymap[4] = value.as_action()?,
+            ConfigKey::ProfileName          => profile.name = profile_name(value.as_text()?)?,
+            ConfigKey::IdleTimeout          => self.power.idle_timeout_s = value.as_u16_in(IDLE_TIMEOUT_RANGE)?,
+            ConfigKey::IdleGyroThreshold    => self.power.idle_gyro_threshold = value.as_f32_in(GYRO_THRESHOLD_RANGE)?,
+            ConfigKey::WomThreshold         => self.power.wom_threshold_mg = value.as_u16_in(WOM_THRESHOLD_RANGE)?,
+            ConfigKey::TelemetryRate        => self.telemetry_rate = value.as_u16_in(0..=READ_FREQ as u16)?,
+            ConfigKey::Hand                 => self.link.hand = value.as_hand()?,
+            ConfigKey::PointerHand          => self.link.pointer_hand = value.as_hand()?,
+            ConfigKey::PairedGlove1         => self.link.paired[0] = value.as_u32()?,
+            ConfigKey::PairedGlove2         => self.link.paired[1] = value.as_u32()?,
+            ConfigKey::MidiChannel          => self.midi.channel = value.as_u8_in(1..=16)?,
+            ConfigKey::MidiCc               => self.midi.cc = value.as_u8_in(0..=MAX_MIDI_CC)?,
+            ConfigKey::MidiNote             => self.midi.note = value.as_u8_in(0..=127)?,
+            ConfigKey::WifiNetwork          => self.network.wifi_network = text_setting(value.as_text()?)?,
+            ConfigKey::WifiPassword         => self.network.wifi_password = text_setting(value.as_text()?)?,
+            ConfigKey::DongleIp             => self.network.dongle_ip = value.as_ip()?,
+            ConfigKey::GloveIp              => self.network.glove_ip = value.as_ip()?,
+            ConfigKey::ActiveProfile        => unreachable!("handled above"),
+        }
+        Ok(())
+    }
+
+    fn find_profile(&self, name: &str) -> Option<usize> {
+        self.profiles.iter().position(|profile| profile.is_used() && profile.name == name)
+    }
+
+    /// Makes the named profile active
+    pub fn load_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        self.active_profile = self.find_profile(name).ok_or(ConfigError::UnknownProfile)?;
+        Ok(())
+    }
+
+    /// Copies the active profile under `name`, replacing a profile with that name
+    /// or taking a free slot, and makes the copy active. Returns its slot.
+    pub fn save_profile(&mut self, name: &str) -> Result<usize, ConfigError> {
+        let name = profile_name(name)?;
+        let slot = self.find_profile(&name)
+            .or_else(|| self.profiles.iter().position(|profile| !profile.is_used()))
+            .ok_or(ConfigError::ProfilesFull)?;
+        self.profiles[slot] = Profile { name, ..self.profile().clone() };
+        self.active_profile = slot;
+        Ok(slot)
+    }
+
+    /// Frees the slot of the named profile, which can't be the active one
+    pub fn delete_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        let slot = self.find_profile(name).ok_or(ConfigError::UnknownProfile)?;
+        if slot == self.active_profile {
+            return Err(ConfigError::ProfileInUse);
+        }
+        self.profiles[slot] = Profile::default();
+        Ok(())
+    }
+
+    /// Switches to the next stored profile, wrapping around
+    pub fn next_profile(&mut self) {
+        let count = self.profiles.len();
+        self.active_profile = (1..count)
+            .map(|offset| (self.active_profile + offset) % count)
+            .find(|slot| self.profiles[*slot].is_used())
+            .unwrap_or(self.active_profile);
+    }
+}
+
+/// Fits a text value into a setting of capacity `N`
+fn text_setting<const N: usize>(text: &str) -> Result<String<N>, ConfigError> {
+    String::from_str(text).map_err(|_| ConfigError::OutOfRange)
+}
+
+/// Profile names are single words, as typed on the command line
+fn profile_name(text: &str) -> Result<String<PROFILE_NAME_LEN>, ConfigError> {
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return Err(ConfigError::OutOfRange);
+    }
+    text_setting(text)
+}
+
+
+/// Name and flash id of every setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKey {
+    RollSens,
+    PitchSens,
+    WheelSens,
+    PanSens,
+    DeadZone,
+    PointerCurve,
+    OutputMode,
+    FlexSupBand,
+    FlexLowBand,
+    ThumbAction,
+    IndexAction,
+    MiddleAction,
+    RingAction,
+    PinkyAction,
+    ProfileName,
+    IdleTimeout,
+    IdleGyroThreshold,
+    WomThreshold,
+    TelemetryRate,
+    Hand,
+    PointerHand,
+    PairedGlove1,
+    PairedGlove2,
+    MidiChannel,
+    MidiCc,
+    MidiNote,
+    WifiNetwork,
+    WifiPassword,
+    DongleIp,
+    GloveIp,
+    ActiveProfile,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 31] = [
+        ConfigKey::RollSens, ConfigKey::PitchSens, ConfigKey::WheelSens, ConfigKey::PanSens,
+        ConfigKey::DeadZone, ConfigKey::PointerCurve, ConfigKey::OutputMode,
+        ConfigKey::FlexSupBand, ConfigKey::FlexLowBand,
+        ConfigKey::ThumbAction, ConfigKey::IndexAction, ConfigKey::MiddleAction,
+        ConfigKey::RingAction, ConfigKey::PinkyAction, ConfigKey::ProfileName,
+        ConfigKey::IdleTimeout, ConfigKey::IdleGyroThreshold, ConfigKey::WomThreshold,
+        ConfigKey::TelemetryRate,
+        ConfigKey::Hand, ConfigKey::PointerHand, ConfigKey::PairedGlove1, ConfigKey::PairedGlove2,
+        ConfigKey::MidiChannel, ConfigKey::MidiCc, ConfigKey::MidiNote,
+        ConfigKey::WifiNetwork, ConfigKey::WifiPassword, ConfigKey::DongleIp, ConfigKey::GloveIp,
+        ConfigKey::ActiveProfile,
+    ];
+
+    /// Id stored in flash, never reuse an id for a different setting
+    pub fn id(&self) -> u8 {
+        match self {
+            ConfigKey::RollSens             => 0x01,
+            ConfigKey::PitchSens            => 0x02,
+            ConfigKey::WheelSens            => 0x03,
+            ConfigKey::PanSens              => 0x04,
+            ConfigKey::DeadZone             => 0x05,
+            ConfigKey::PointerCurve         => 0x06,
+            ConfigKey::OutputMode           => 0x07,
+            ConfigKey::FlexSupBand          => 0x10,
+            ConfigKey::FlexLowBand          => 0x11,
+            ConfigKey::ThumbAction          => 0x18,
+            ConfigKey::IndexAction          => 0x19,
+            ConfigKey::MiddleAction         => 0x1A,
+            ConfigKey::RingAction           => 0x1B,
+            ConfigKey::PinkyAction          => 0x1C,
+            ConfigKey::ProfileName          => 0x1F,
+            ConfigKey::IdleTimeout          => 0x20,
+            ConfigKey::IdleGyroThreshold    => 0x21,
+            ConfigKey::WomThreshold         => 0x22,
+            ConfigKey::TelemetryRate        => 0x24,
+            ConfigKey::Hand                 => 0x28,
+            ConfigKey::PointerHand          => 0x29,
+            ConfigKey::PairedGlove1         => 0x2A,
+            ConfigKey::PairedGlove2         => 0x2B,
+            ConfigKey::MidiChannel          => 0x2C,
+            ConfigKey::MidiCc               => 0x2D,
+            ConfigKey::MidiNote             => 0x2E,
+            ConfigKey::WifiNetwork          => 0x30,
+            ConfigKey::WifiPassword         => 0x31,
+            ConfigKey::DongleIp             => 0x32,
+            ConfigKey::GloveIp              => 0x33,
+            ConfigKey::ActiveProfile        => 0x40,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.id() == id)
+    }
+
+    /// Whether each profile holds its own value, ids below 0x20 are reserved for them
+    pub fn is_profile_field(&self) -> bool {
+        self.id() < 0x20
+    }
+
+    /// Whether the setting is only read while the network comes up, ids 0x30 to 0x3F
+    pub fn is_network_field(&self) -> bool {
+        (0x30..0x40).contains(&self.id())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigKey::RollSens             => "roll_sens",
+            ConfigKey::PitchSens            => "pitch_sens",
+            ConfigKey::WheelSens            => "wheel_sens",
+            ConfigKey::PanSens              => "pan_sens",
+            ConfigKey::DeadZone             => "dead_zone",
+            ConfigKey::PointerCurve         => "pointer_curve",
+            ConfigKey::OutputMode           => "output_mode",
+            ConfigKey::FlexSupBand          => "flex_sup_band",
+            ConfigKey::FlexLowBand          => "flex_low_band",
+            ConfigKey::ThumbAction          => "thumb_action",
+            ConfigKey::IndexAction          => "index_action",
+            ConfigKey::MiddleAction         => "middle_action",
+            ConfigKey::RingAction           => "ring_action",
+            ConfigKey::PinkyAction          => "pinky_action",
+            ConfigKey::ProfileName          => "profile_name",
+            ConfigKey::IdleTimeout          => "idle_timeout",
+            ConfigKey::IdleGyroThreshold    => "idle_gyro_threshold",
+            ConfigKey::WomThreshold         => "wom_threshold",
+            ConfigKey::TelemetryRate        => "telemetry_rate",
+            ConfigKey::Hand                 => "hand",
+            ConfigKey::PointerHand          => "pointer_hand",
+            ConfigKey::PairedGlove1         => "paired_glove_1",
+            ConfigKey::PairedGlove2         => "paired_glove_2",
+            ConfigKey::MidiChannel          => "midi_channel",
+            ConfigKey::MidiCc               => "midi_cc",
+            ConfigKey::MidiNote             => "midi_note",
+            ConfigKey::WifiNetwork          => "wifi_network",
+            ConfigKey::WifiPassword         => "wifi_password",
+            ConfigKey::DongleIp             => "dongle_ip",
+            ConfigKey::GloveIp              => "glove_ip",
+            ConfigKey::ActiveProfile        => "active_profile",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.name() == name)
+    }
+}
+
+
+impl FingerAction {
+    /// Encodes as [kind][code], consumer usages take a two byte code
+    fn to_be_bytes(self) -> Vec<u8, ACTION_SIZE> {
+        let [high, low] = match self {
+            FingerAction::Consumer(usage) => usage.to_be_bytes(),
+            _ => [0, 0],
+        };
+        let data: &[u8] = match self {
+            FingerAction::None              => &[0, 0],
+            FingerAction::LeftClick         => &[1, 0],
+            FingerAction::RightClick        => &[2, 0],
+            FingerAction::MiddleClick       => &[3, 0],
+            FingerAction::Modifier(bits)    => &[4, bits],
+            FingerAction::Key(usage)        => &[5, usage],
+            FingerAction::Consumer(_)       => &[6, high, low],
+            FingerAction::System(usage)     => &[7, usage],
+        };
+        // Every encoding fits ACTION_SIZE
+        Vec::from_slice(data).unwrap()
+    }
+
+    fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [0, _] => Some(FingerAction::None),
+            [1, _] => Some(FingerAction::LeftClick),
+            [2, _] => Some(FingerAction::RightClick),
+            [3, _] => Some(FingerAction::MiddleClick),
+            [4, bits] => Some(FingerAction::Modifier(bits)),
+            [5, usage] => Some(FingerAction::Key(usage)),
+            [6, high, low] => Some(FingerAction::Consumer(u16::from_be_bytes([high, low]))),
+            [7, usage] => Some(FingerAction::System(usage)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for FingerAction {
+    type Err = ConfigError;
+
+    /// Parses the names shown by `Display`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let action = match text {
+            "none"          => FingerAction::None,
+            "left_click"    => FingerAction::LeftClick,
+            "right_click"   => FingerAction::RightClick,
+            "middle_click"  => FingerAction::MiddleClick,
+            "ctrl"          => FingerAction::Modifier(MOD_CTRL),
+            "shift"         => FingerAction::Modifier(MOD_SHIFT),
+            "alt"           => FingerAction::Modifier(MOD_ALT),
+            "gui"           => FingerAction::Modifier(MOD_GUI),
+            "next_track"    => FingerAction::Consumer(CONSUMER_NEXT_TRACK),
+            "prev_track"    => FingerAction::Consumer(CONSUMER_PREV_TRACK),
+            "play_pause"    => FingerAction::Consumer(CONSUMER_PLAY_PAUSE),
+            "mute"          => FingerAction::Consumer(CONSUMER_MUTE),
+            "volume_up"     => FingerAction::Consumer(CONSUMER_VOLUME_UP),
+            "volume_down"   => FingerAction::Consumer(CONSUMER_VOLUME_DOWN),
+            "power_down"    => FingerAction::System(SYSTEM_POWER_DOWN),
+            "sleep"         => FingerAction::System(SYSTEM_SLEEP),
+            "wake_up"       => FingerAction::System(SYSTEM_WAKE_UP),
+            _ => match text.split_once(':') {
+                Some(("mod", bits)) => FingerAction::Modifier(bits.parse().map_err(|_| ConfigError::WrongType)?),
+                Some(("key", usage)) => FingerAction::Key(usage.parse().map_err(|_| ConfigError::WrongType)?),
+                Some(("consumer", usage)) => FingerAction::Consumer(usage.parse().map_err(|_| ConfigError::WrongType)?),
+                Some(("system", usage)) => FingerAction::System(usage.parse().map_err(|_| ConfigError::WrongType)?),
+                _ => return Err(ConfigError::WrongType),
+            },
+        };
+        Ok(action)
+    }
+}
+
+impl fmt::Display for FingerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FingerAction::None => write!(f, "none"),
+            FingerAction::LeftClick => write!(f, "left_click"),
+            FingerAction::RightClick => write!(f, "right_click"),
+            FingerAction::MiddleClick => write!(f, "middle_click"),
+            FingerAction::Modifier(MOD_CTRL) => write!(f, "ctrl"),
+            FingerAction::Modifier(MOD_SHIFT) => write!(f, "shift"),
+            FingerAction::Modifier(MOD_ALT) => write!(f, "alt"),
+            FingerAction::Modifier(MOD_GUI) => write!(f, "gui"),
+            FingerAction::Modifier(bits) => write!(f, "mod:{}", bits),
+            FingerAction::Key(usage) => write!(f, "key:{}", usage),
+            FingerAction::Consumer(CONSUMER_NEXT_TRACK) => write!(f, "next_track"),
+            FingerAction::Consumer(CONSUMER_PREV_TRACK) => write!(f, "prev_track"),
+            FingerAction::Consumer(CONSUMER_PLAY_PAUSE) => write!(f, "play_pause"),
+            FingerAction::Consumer(CONSUMER_MUTE) => write!(f, "mute"),
+            FingerAction::Consumer(CONSUMER_VOLUME_UP) => write!(f, "volume_up"),
+            FingerAction::Consumer(CONSUMER_VOLUME_DOWN) => write!(f, "volume_down"),
+            FingerAction::Consumer(usage) => write!(f, "consumer:{}", usage),
+            FingerAction::System(SYSTEM_POWER_DOWN) => write!(f, "power_down"),
+            FingerAction::System(SYSTEM_SLEEP) => write!(f, "sleep"),
+            FingerAction::System(SYSTEM_WAKE_UP) => write!(f, "wake_up"),
+            FingerAction::System(usage) => write!(f, "system:{}", usage),
+        }
+    }
+}
+
+
+impl FromStr for Hand {
+    type Err = ConfigError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "left" => Ok(Hand::Left),
+            "right" => Ok(Hand::Right),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+}
+
+impl fmt::Display for Hand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hand::Left => write!(f, "left"),
+            Hand::Right => write!(f, "right"),
+        }
+    }
+}
+
+impl Hand {
+    pub fn to_be_bytes(self) -> [u8; 1] {
+        match self {
+            Hand::Left => [0],
+            Hand::Right => [1],
+        }
+    }
+
+    pub fn from_be_bytes(data: [u8; 1]) -> Option<Self> {
+        match data[0] {
+            0 => Some(Hand::Left),
+            1 => Some(Hand::Right),
+            _ => None,
+        }
+    }
+}
+
+
+impl FromStr for OutputMode {
+    type Err = ConfigError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "pointer" => Ok(OutputMode::Pointer),
+            "gamepad" => Ok(OutputMode::Gamepad),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputMode::Pointer => write!(f, "pointer"),
+            OutputMode::Gamepad => write!(f, "gamepad"),
+        }
+    }
+}
+
+impl OutputMode {
+    pub fn to_be_bytes(self) -> [u8; 1] {
+        match self {
+            OutputMode::Pointer => [0],
+            OutputMode::Gamepad => [1],
+        }
+    }
+
+    pub fn from_be_bytes(data: [u8; 1]) -> Option<Self> {
+        match data[0] {
+            0 => Some(OutputMode::Pointer),
+            1 => Some(OutputMode::Gamepad),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    Float(f32),
+    Int(u32),
+    Text(String<WIFI_PASSWORD_LEN>),
+    Ip(Ipv4Addr),
+    Action(FingerAction),
+    Hand(Hand),
+    Mode(OutputMode),
+}
+
+impl ConfigValue {
+    fn as_float(&self) -> Result<f32, ConfigError> {
+        match self {
+            ConfigValue::Float(value) if value.is_finite() => Ok(*value),
+            ConfigValue::Float(_) => Err(ConfigError::OutOfRange),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_f32_in(&self, range: RangeInclusive<f32>) -> Result<f32, ConfigError> {
+        let value = self.as_float()?;
+        range.contains(&value).then_some(value).ok_or(ConfigError::OutOfRange)
+    }
+
+    fn as_u32(&self) -> Result<u32, ConfigError> {
+        match self {
+            ConfigValue::Int(value) => Ok(*value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_u16(&self) -> Result<u16, ConfigError> {
+        match self {
+            ConfigValue::Int(value) => u16::try_from(*value).map_err(|_| ConfigError::OutOfRange),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_u16_in(&self, range: RangeInclusive<u16>) -> Result<u16, ConfigError> {
+        let value = self.as_u16()?;
+        range.contains(&value).then_some(value).ok_or(ConfigError::OutOfRange)
+    }
+
+    fn as_u8_in(&self, range: RangeInclusive<u8>) -> Result<u8, ConfigError> {
+        let value = self.as_u16_in(*range.start() as u16..=*range.end() as u16)?;
+        Ok(value as u8)
+    }
+
+    fn as_text(&self) -> Result<&str, ConfigError> {
+        match self {
+            ConfigValue::Text(value) => Ok(value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_ip(&self) -> Result<Ipv4Addr, ConfigError> {
+        match self {
+            ConfigValue::Ip(value) => Ok(*value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_action(&self) -> Result<FingerAction, ConfigError> {
+        match self {
+            ConfigValue::Action(value) => Ok(*value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_hand(&self) -> Result<Hand, ConfigError> {
+        match self {
+            ConfigValue::Hand(value) => Ok(*value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    fn as_mode(&self) -> Result<OutputMode, ConfigError> {
+        match self {
+            ConfigValue::Mode(value) => Ok(*value),
+            _ => Err(ConfigError::WrongType),
+        }
+    }
+
+    /// Parses `text` into a value of the same type as `self`
+    pub fn parse_as(&self, text: &str) -> Result<ConfigValue, ConfigError> {
+        match self {
+            ConfigValue::Float(_) => text.parse().map(ConfigValue::Float).map_err(|_| ConfigError::WrongType),
+            ConfigValue::Int(_) => text.parse().map(ConfigValue::Int).map_err(|_| ConfigError::WrongType),
+            ConfigValue::Text(_) => text_setting(text).map(ConfigValue::Text),
+            ConfigValue::Ip(_) if text == AUTO_IP => Ok(ConfigValue::Ip(Ipv4Addr::UNSPECIFIED)),
+            ConfigValue::Ip(_) => text.parse().map(ConfigValue::Ip).map_err(|_| ConfigError::WrongType),
+            ConfigValue::Action(_) => text.parse().map(ConfigValue::Action),
+            ConfigValue::Hand(_) => text.parse().map(ConfigValue::Hand),
+            ConfigValue::Mode(_) => text.parse().map(ConfigValue::Mode),
+        }
+    }
+
+    /// Encodes as [type tag][big endian value], returns the written length
+    pub fn to_bytes(&self, buf: &mut [u8; MAX_VALUE_SIZE]) -> usize {
+        let mut encode = |tag: u8, data: &[u8]| {
+            buf[0] = tag;
+            buf[1..1 + data.len()].copy_from_slice(data);
+            1 + data.len()
+        };
+        match self {
+            ConfigValue::Float(value) => encode(TYPE_FLOAT, &value.to_be_bytes()),
+            ConfigValue::Int(value) => encode(TYPE_INT, &value.to_be_bytes()),
+            ConfigValue::Text(value) => encode(TYPE_TEXT, value.as_bytes()),
+            ConfigValue::Ip(value) => encode(TYPE_IP, &value.octets()),
+            ConfigValue::Action(value) => encode(TYPE_ACTION, &value.to_be_bytes()),
+            ConfigValue::Hand(value) => encode(TYPE_HAND, &value.to_be_bytes()),
+            ConfigValue::Mode(value) => encode(TYPE_MODE, &value.to_be_bytes()),
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let (tag, data) = data.split_first()?;
+        match *tag {
+            TYPE_FLOAT => Some(ConfigValue::Float(f32::from_be_bytes(data.try_into().ok()?))),
+            TYPE_INT => Some(ConfigValue::Int(u32::from_be_bytes(data.try_into().ok()?))),
+            TYPE_TEXT => {
+                let text = core::str::from_utf8(data).ok()?;
+                Some(ConfigValue::Text(String::from_str(text).ok()?))
+            }
+            TYPE_IP => Some(ConfigValue::Ip(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?))),
+            TYPE_ACTION => Some(ConfigValue::Action(FingerAction::from_be_bytes(data)?)),
+            TYPE_HAND => Some(ConfigValue::Hand(Hand::from_be_bytes(data.try_into().ok()?)?)),
+            TYPE_MODE => Some(ConfigValue::Mode(OutputMode::from_be_bytes(data.try_into().ok()?)?)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValue::Float(value) => write!(f, "{}", value),
+            ConfigValue::Int(value) => write!(f, "{}", value),
+            ConfigValue::Text(value) => write!(f, "{}", value),
+            ConfigValue::Ip(value) if value.is_unspecified() => write!(f, "{}", AUTO_IP),
+            ConfigValue::Ip(value) => write!(f, "{}", value),
+            ConfigValue::Action(value) => write!(f, "{}", value),
+            ConfigValue::Hand(value) => write!(f, "{}", value),
+            ConfigValue::Mode(value) => write!(f, "{}", value),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+
+use crate::{
+    config::{Settings, ConfigKey, ConfigValue, MAX_VALUE_SIZE},
+    definitions::MAX_PROFILES,
+};
+
+// The store is a ring of flash sectors, only the one with the highest sequence is active.
+// Sector:  [magic: u32][sequence: u32][schema: u8][padding: 3] followed by records
+// Record:  [key id: u8][profile slot: u8][value length: u8][value][crc32: u32] padded to RECORD_ALIGN
+// Settings are appended as records, the last valid record of a key wins. A full sector
+// is compacted into the next one, spreading erases over the whole region.
+// Schema 1 records had no profile slot byte.
+
+/// Bumped whenever a setting changes meaning or type, see `migrate`
+pub const SCHEMA_VERSION: u8        = 2;
+const SECTOR_MAGIC: u32             = 0x4D47_4346; // "MGCF"
+const HEADER_SIZE: usize            = 12;
+const RECORD_HEADER_SIZE: usize     = 3;
+const V1_RECORD_HEADER_SIZE: usize  = 2;
+const CRC_SIZE: usize               = 4;
+const RECORD_ALIGN: usize           = 4;
+const MAX_RECORD_SIZE: usize        = record_size(RECORD_HEADER_SIZE, MAX_VALUE_SIZE);
+const ERASED: u8                    = 0xFF;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug)]
+pub enum StoreError {
+    Flash(NorFlashErrorKind),
+}
+
+const fn record_size(header_size: usize, value_len: usize) -> usize {
+    (header_size + value_len + CRC_SIZE).next_multiple_of(RECORD_ALIGN)
+}
+
+fn record_header_size(schema: u8) -> usize {
+    match schema {
+        1 => V1_RECORD_HEADER_SIZE,
+        _ => RECORD_HEADER_SIZE,
+    }
+}
+
+/// Builds a record into `buf`, returns its padded size.
+/// `slot` is only meaningful for profile fields.
+fn encode_record(key: ConfigKey, slot: usize, value: &ConfigValue, buf: &mut [u8; MAX_RECORD_SIZE]) -> usize {
+    let mut data = [0; MAX_VALUE_SIZE];
+    let len = value.to_bytes(&mut data);
+    let size = record_size(RECORD_HEADER_SIZE, len);
+    let crc_at = RECORD_HEADER_SIZE + len;
+
+    buf.fill(ERASED);
+    buf[0] = key.id();
+    buf[1] = slot as u8;
+    buf[2] = len as u8;
+    buf[RECORD_HEADER_SIZE..crc_at].copy_from_slice(&data[..len]);
+    let crc = CRC.checksum(&buf[..crc_at]);
+    buf[crc_at..crc_at + CRC_SIZE].copy_from_slice(&crc.to_be_bytes());
+    size
+}
+
+/// Converts a record written under an older schema into the current one.
+/// Returns None for records that can't be carried over.
+fn migrate(schema: u8, key: u8, slot: u8, value: ConfigValue) -> Option<(ConfigKey, usize, ConfigValue)> {
+    let key = ConfigKey::from_id(key)?;
+    match schema {
+        SCHEMA_VERSION if (slot as usize) < MAX_PROFILES => Some((key, slot as usize, value)),
+        // Before profiles, pointer and flex settings become the default profile
+        1 => Some((key, 0, value)),
+        _ => None,
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct ActiveSector {
+    sector: u32,
+    sequence: u32,
+    schema: u8,
+    /// Offset of the first free byte in the sector
+    write_pos: u32,
+}
+
+/// Wear levelled, CRC protected settings store over a flash region
+pub struct ConfigStore<F> {
+    flash: F,
+    offset: u32,
+    sectors: u32,
+    active: Option<ActiveSector>,
+}
+
+impl<F: NorFlash> ConfigStore<F> {
+    /// Mounts the store living in `size` bytes of flash starting at `offset`
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+        const { assert!(RECORD_ALIGN % F::WRITE_SIZE == 0 && HEADER_SIZE % RECORD_ALIGN == 0) };
+        let mut store = ConfigStore {
+            flash,
+            offset,
+            sectors: size / F::ERASE_SIZE as u32,
+            active: None,
+        };
+        store.active = store.find_active();
+        store
+    }
+
+    fn sector_start(&self, sector: u32) -> u32 {
+        self.offset + sector * F::ERASE_SIZE as u32
+    }
+
+    /// Reads the sequence and schema of a committed sector
+    fn read_header(&mut self, sector: u32) -> Option<(u32, u8)> {
+        let mut header = [0; HEADER_SIZE];
+        self.flash.read(self.sector_start(sector), &mut header).ok()?;
+        let magic = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        if magic != SECTOR_MAGIC {
+            return None;
+        }
+        Some((u32::from_be_bytes([header[4], header[5], header[6], header[7]]), header[8]))
+    }
+
+    fn find_active(&mut self) -> Option<ActiveSector> {
+        let mut active: Option<ActiveSector> = None;
+        for sector in 0..self.sectors {
+            let Some((sequence, schema)) = self.read_header(sector) else { continue };
+            if active.is_none_or(|active| sequence > active.sequence) {
+                active = Some(ActiveSector { sector, sequence, schema, write_pos: HEADER_SIZE as u32 });
+            }
+        }
+        let mut active = active?;
+        active.write_pos = self.scan(active.sector, active.schema, |_, _, _| {});
+        Some(active)
+    }
+
+    /// Calls `f` with the key id, profile slot and value of every record with a valid CRC,
+    /// in write order. Returns the offset where the next record would go.
+    fn scan(&mut self, sector: u32, schema: u8, mut f: impl FnMut(u8, u8, &[u8])) -> u32 {
+        let sector_size = F::ERASE_SIZE as u32;
+        let start = self.sector_start(sector);
+        let header_size = record_header_size(schema);
+        let mut record = [0; MAX_RECORD_SIZE];
+        let mut pos = HEADER_SIZE as u32;
+
+        while pos + header_size as u32 <= sector_size {
+            if self.flash.read(start + pos, &mut record[..header_size]).is_err() {
+                return sector_size;
+            }
+            let key = record[0];
+            if key == ERASED {
+                return pos;
+            }
+            // Schema 1 records have no slot
+            let (slot, len) = match schema {
+                1 => (0, record[1] as usize),
+                _ => (record[1], record[2] as usize),
+            };
+            let size = record_size(header_size, len);
+            if len > MAX_VALUE_SIZE || pos + size as u32 > sector_size {
+                // Torn write, nothing after it can be trusted
+                return sector_size;
+            }
+            if self.flash.read(start + pos, &mut record[..size]).is_err() {
+                return sector_size;
+            }
+            let crc_at = header_size + len;
+            let crc = u32::from_be_bytes([record[crc_at], record[crc_at + 1], record[crc_at + 2], record[crc_at + 3]]);
+            if CRC.checksum(&record[..crc_at]) == crc {
+                f(key, slot, &record[header_size..crc_at]);
+            }
+            pos += size as u32;
+        }
+        sector_size
+    }
+
+    /// Stored settings on top of the defaults, migrating older schemas
+    pub fn load(&mut self) -> Settings {
+        let mut settings = Settings::default();
+        let Some(active) = self.active else {
+            return settings;
+        };
+        if active.schema > SCHEMA_VERSION {
+            // Written by newer firmware, can't be understood
+            return settings;
+        }
+        self.scan(active.sector, active.schema, |key, slot, data| {
+            let Some(value) = ConfigValue::from_bytes(data) else { return };
+            if let Some((key, slot, value)) = migrate(active.schema, key, slot, value) {
+                // Invalid values keep their default
+                let _ = settings.set_in(slot, key, value);
+            }
+        });
+        if active.schema < SCHEMA_VERSION {
+            // Retried on next boot if it fails, older records are still readable
+            let _ = self.rewrite(&settings);
+        }
+        settings
+    }
+
+    /// Persists `key` as held in `settings`, which must be the loaded settings
+    /// with any later changes applied, as a full sector is rebuilt from them.
+    /// Profile fields are saved for the active profile.
+    pub fn save(&mut self, settings: &Settings, key: ConfigKey) -> Result<(), StoreError> {
+        let mut record = [0; MAX_RECORD_SIZE];
+        let slot = if key.is_profile_field() { settings.active_profile } else { 0 };
+        let size = encode_record(key, slot, &settings.get(key), &mut record);
+
+        match self.active {
+            Some(active) if active.schema == SCHEMA_VERSION
+                && active.write_pos as usize + size <= F::ERASE_SIZE =>
+            {
+                let start = self.sector_start(active.sector);
+                self.flash.write(start + active.write_pos, &record[..size])
+                    .map_err(|e| StoreError::Flash(e.kind()))?;
+                self.active = Some(ActiveSector { write_pos: active.write_pos + size as u32, ..active });
+                Ok(())
+            }
+            // Full, blank or older schema sector
+            _ => self.rewrite(settings),
+        }
+    }
+
+    /// Writes every non default setting into the next sector and makes it active
+    pub fn rewrite(&mut self, settings: &Settings) -> Result<(), StoreError> {
+        let (sector, sequence) = match self.active {
+            Some(active) => ((active.sector + 1) % self.sectors, active.sequence.wrapping_add(1)),
+            None => (0, 1),
+        };
+        let start = self.sector_start(sector);
+        self.flash.erase(start, start + F::ERASE_SIZE as u32)
+            .map_err(|e| StoreError::Flash(e.kind()))?;
+
+        let defaults = Settings::default();
+        let mut record = [0; MAX_RECORD_SIZE];
+        let mut pos = HEADER_SIZE as u32;
+        // Profiles go first, the active profile record needs its profile loaded
+        let profile_fields = (0..MAX_PROFILES)
+            .flat_map(|slot| ConfigKey::ALL.into_iter().filter(|key| key.is_profile_field()).map(move |key| (key, slot)));
+        let globals = ConfigKey::ALL.into_iter().filter(|key| !key.is_profile_field()).map(|key| (key, 0));
+        for (key, slot) in profile_fields.chain(globals) {
+            let value = settings.get_in(slot, key);
+            if value == defaults.get_in(slot, key) {
+                continue;
+            }
+            let size = encode_record(key, slot, &value, &mut record);
+            self.flash.write(start + pos, &record[..size])
+                .map_err(|e| StoreError::Flash(e.kind()))?;
+            pos += size as u32;
+        }
+
+        // Header goes last, a power loss before this keeps the previous sector active
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(&SECTOR_MAGIC.to_be_bytes());
+        header[4..8].copy_from_slice(&sequence.to_be_bytes());
+        header[8] = SCHEMA_VERSION;
+        self.flash.write(start, &header)
+            .map_err(|e| StoreError::Flash(e.kind()))?;
+
+        self.active = Some(ActiveSector { sector, sequence, schema: SCHEMA_VERSION, write_pos: pos });
+        Ok(())
+    }
+}
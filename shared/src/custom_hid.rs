@@ -6,15 +6,136 @@ use usbd_hid::descriptor::{
 
 pub type HidInstructionArr = [u8; 16];
 
+/// Keyboard usages an NKRO report holds, letters up to the lock keys
+pub const NKRO_KEYS: usize = 128;
+pub const KEY_BITMAP_SIZE: usize = NKRO_KEYS / 8;
+
+/// Set of held keyboard usages below `NKRO_KEYS`, one bit each
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyBitmap(pub [u8; KEY_BITMAP_SIZE]);
+
+impl KeyBitmap {
+    pub const fn new() -> Self {
+        KeyBitmap([0; KEY_BITMAP_SIZE])
+    }
+
+    /// Usages past the bitmap are dropped
+    pub fn press(&mut self, usage: u8) {
+        if (usage as usize) < NKRO_KEYS {
+            self.0[usage as usize / 8] |= 1 << (usage % 8);
+        }
+    }
+
+    /// Adds every key held in `other`
+    pub fn merge(&mut self, other: &KeyBitmap) {
+        for (byte, other) in self.0.iter_mut().zip(other.0) {
+            *byte |= other;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+}
+
+// Consumer page usages with a name in the keymap
+pub const CONSUMER_NEXT_TRACK: u16  = 0xB5;
+pub const CONSUMER_PREV_TRACK: u16  = 0xB6;
+pub const CONSUMER_PLAY_PAUSE: u16  = 0xCD;
+pub const CONSUMER_MUTE: u16        = 0xE2;
+pub const CONSUMER_VOLUME_UP: u16   = 0xE9;
+pub const CONSUMER_VOLUME_DOWN: u16 = 0xEA;
+
+// System Control usages of the Generic Desktop page
+pub const SYSTEM_POWER_DOWN: u8 = 0x81;
+pub const SYSTEM_SLEEP: u8      = 0x82;
+pub const SYSTEM_WAKE_UP: u8    = 0x83;
+
+// Keyboard LED output report bits
+pub const LED_NUM_LOCK: u8      = 0x01;
+pub const LED_CAPS_LOCK: u8     = 0x02;
+pub const LED_SCROLL_LOCK: u8   = 0x04;
+
+/// Finger axes of a gamepad report, thumb to pinky
+pub const GAMEPAD_FINGERS: usize = 5;
+/// Gamepad button of the thumb and index tap, after one per finger
+pub const GAMEPAD_TAP_BUTTON: u16 = 1 << GAMEPAD_FINGERS;
+pub type GamepadReportArr = [u8; 2 + 3 * 2 + GAMEPAD_FINGERS];
+
+/// Glove pose, bend of each finger and buttons, streamed to the dongle gamepad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GamepadReport {
+    /// Button n + 1 is held while bit n is set
+    pub buttons: u16,
+    /// Roll, pitch and yaw, full scale is ±180°
+    pub orientation: [i16; 3],
+    /// How far each finger is bent, 0 open to 255 closed
+    pub fingers: [u8; GAMEPAD_FINGERS],
+}
+
+impl GamepadReport {
+    /// Centered and released
+    pub const fn new() -> Self {
+        GamepadReport { buttons: 0, orientation: [0; 3], fingers: [0; GAMEPAD_FINGERS] }
+    }
+
+    pub fn from_be_bytes(data: GamepadReportArr) -> Self {
+        let axis = |at: usize| i16::from_be_bytes([data[at], data[at + 1]]);
+        let mut fingers = [0; GAMEPAD_FINGERS];
+        fingers.copy_from_slice(&data[8..]);
+        GamepadReport {
+            buttons:        u16::from_be_bytes([data[0], data[1]]),
+            orientation:    [axis(2), axis(4), axis(6)],
+            fingers,
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> GamepadReportArr {
+        let mut data = [0; size_of::<GamepadReportArr>()];
+        data[..2].copy_from_slice(&self.buttons.to_be_bytes());
+        for (chunk, axis) in data[2..8].chunks_exact_mut(2).zip(self.orientation) {
+            chunk.copy_from_slice(&axis.to_be_bytes());
+        }
+        data[8..].copy_from_slice(&self.fingers);
+        data
+    }
+}
+
 #[derive(Debug)]
 pub struct HidInstruction {
     pub mouse: MouseReport,
     pub keyboard: KeyboardReport,
+    /// Consumer usage held until a report without it
     pub media: MediaKeyboardReport,
+    /// System Control usage held, 0 for none
+    pub system: u8,
+    /// Keys held past the six `keyboard.keycodes`, only sent on the wire when any is
+    pub extra_keys: KeyBitmap,
 }
 
 impl HidInstruction {
-    /// Build HidInstruction from big endian bytes [u8;16]
+    /// Nothing pressed and no movement
+    pub const fn released() -> Self {
+        HidInstruction {
+            mouse: MouseReport { buttons: 0, x: 0, y: 0, wheel: 0, pan: 0 },
+            keyboard: KeyboardReport { modifier: 0, reserved: 0, leds: 0, keycodes: [0; 6] },
+            media: MediaKeyboardReport { usage_id: 0 },
+            system: 0,
+            extra_keys: KeyBitmap::new(),
+        }
+    }
+
+    /// Every held key, the six keycodes and the extra ones
+    pub fn keys(&self) -> KeyBitmap {
+        let mut keys = self.extra_keys;
+        for &code in self.keyboard.keycodes.iter().filter(|code| **code != 0) {
+            keys.press(code);
+        }
+        keys
+    }
+
+    /// Build HidInstruction from big endian bytes [u8;16], with no extra keys.
+    /// The keyboard LEDs only travel from the host, their byte carries the system usage.
     pub fn from_be_bytes(data: HidInstructionArr) -> Self {
         let mouse = MouseReport {
             buttons:    u8::from_be(data[0]),
@@ -26,14 +147,16 @@ impl HidInstruction {
         let keyboard = KeyboardReport {
             modifier:   u8::from_be(data[5]),
             reserved:   u8::from_be(data[6]),
-            leds:       u8::from_be(data[7]),
+            leds:       0,
             keycodes:   [data[8], data[9], data[10], data[11], data[12], data[13]]
         };
         let media = MediaKeyboardReport {
             usage_id:   u16::from_be_bytes([data[14], data[15]])
         };
 
-        HidInstruction { mouse, keyboard, media }
+        let system = u8::from_be(data[7]);
+
+        HidInstruction { mouse, keyboard, media, system, extra_keys: KeyBitmap::new() }
     }
 
     /// Converts HidInstruction to big endian bytes [u8;16]
@@ -46,14 +169,14 @@ impl HidInstruction {
         
         let keyboard_modifier        = self.keyboard.modifier.to_be();
         let keyboard_reserved        = self.keyboard.reserved.to_be();
-        let keyboard_leds            = self.keyboard.leds.to_be();
+        let system                   = self.system.to_be();
         let keyboard_keycode    = self.keyboard.keycodes;
 
         let media_usage_id      = self.media.usage_id.to_be_bytes();
 
         [
             mouse_buttons, mouse_x, mouse_y, mouse_wheel, mouse_pan,
-            keyboard_modifier, keyboard_reserved, keyboard_leds,
+            keyboard_modifier, keyboard_reserved, system,
             keyboard_keycode[0], keyboard_keycode[1], keyboard_keycode[2], 
             keyboard_keycode[3], keyboard_keycode[4], keyboard_keycode[5],
             media_usage_id[0], media_usage_id[1]
@@ -1,19 +1,51 @@
 use embassy_time::Duration;
 
+// Network settings are provisioned through the command line, these are first boot defaults
+
 pub const WIFI_NETWORK: &str            = "MotionGlove-Network";
 pub const WIFI_PASSWORD: &str           = "Password123";
 pub const TCP_CHANNEL: u8               = 5;
 pub const TCP_ENDPOINT: u16             = 50124;
 pub const SOCKET_TIMEOUT: Duration      = Duration::from_secs(15);
-pub const DONGLE_IP: &str               = "192.168.0.10";
-pub const GLOVE_IP: &str                = "192.168.0.12";
+pub const DONGLE_IP: &str               = "0.0.0.0";  // Unspecified takes an address from DHCP
+pub const GLOVE_IP: &str                = "0.0.0.0";
+pub const DISCOVERY_PORT: u16           = 50125;      // UDP port of the dongle beacon
+pub const BEACON_PERIOD: Duration       = Duration::from_secs(1);
+pub const DISCOVERY_TIMEOUT: Duration   = Duration::from_secs(5); // Wait for a beacon before retrying
 pub const CHANNEL_SIZE: usize           = 1;
+pub const HID_QUEUE_SIZE: usize         = 16;   // Merged reports waiting for USB, their motion is summed while it is busy
 pub const READ_FREQ: u64                = 1000;
-pub const PADDING_FREQ: u64             = 10;
-pub const DELTA_TIME: f32               = 1.0 / READ_FREQ as f32;
+pub const PADDING_FREQ: u64             = 10;   // Scroll steps per second the wheel and pan sensitivities are tuned for
+pub const SCROLL_MULTIPLIER: u8         = 8;    // Hi-res scroll units per wheel detent
 pub const MOUSE_POLL_MS: u8             = 1;
+pub const BATTERY_POLL_MS: u8           = 255;
 pub const ROLL_SENS: f32                = 30.0; // Pixel movement per roll angle
 pub const PITCH_SENS: f32               = 50.0; // Pixel movement per pitch angle
 pub const WHEEL_SENS: f32               = 40.0;
 pub const PAN_SENS: f32                 = 40.0;
-pub const DEAD_ZONE: f32                = 2.5;
\ No newline at end of file
+pub const DEAD_ZONE: f32                = 2.5;
+pub const POINTER_CURVE: f32            = 1.2;  // Exponent of the angle past the dead zone
+pub const FLEX_SUP_BAND: u16            = 900;  // Flex Schmitt trigger bands
+pub const FLEX_LOW_BAND: u16            = 650;
+pub const IDLE_TIMEOUT: Duration        = Duration::from_secs(60);   // Stillness before sleeping
+pub const IDLE_GYRO_THRESHOLD: f32      = 3.0;  // Rotation [deg/s] still considered as idle
+pub const WOM_THRESHOLD_MG: u16         = 64;   // Acceleration change that wakes the glove
+pub const MAX_GLOVES: usize             = 2;    // Gloves a dongle serves at once
+pub const HEARTBEAT_PERIOD: Duration    = Duration::from_millis(250);
+pub const LINK_TIMEOUT: Duration        = Duration::from_millis(750); // Silence before a link is dropped
+pub const SLEEP_LINK_TIMEOUT: Duration  = Duration::from_secs(5); // Same for a sleeping glove, its WiFi power save delays the heartbeat
+pub const LINK_STATS_WINDOW: u32        = 40;   // Pings per packet loss figure
+pub const RELAY_TIMEOUT: Duration       = Duration::from_secs(1); // Wait for the glove to answer a request
+pub const CALIBRATION_TIME: Duration    = Duration::from_secs(5); // Opening and closing the hand to measure the flex bands
+pub const PAIRING_WINDOW: Duration      = Duration::from_secs(30); // A new glove may pair after the pair command
+pub const MIDI_CHANNEL: u8              = 1;    // 1 to 16
+pub const MIDI_CC: u8                   = 20;   // Control change of the thumb, the other fingers follow
+pub const MIDI_NOTE: u8                 = 60;   // Note played by the tap, middle C
+pub const TELEMETRY_RATE: u16           = 0;    // Telemetry samples per second, off until a host app asks
+pub const MAX_PROFILES: usize           = 4;
+pub const DEFAULT_PROFILE: &str         = "default";
+pub const PROFILE_SWITCH_TAPS: u8       = 3;    // Quick taps that switch to the next profile
+pub const TAP_GAP: Duration             = Duration::from_millis(400);
+pub const FLASH_SIZE: usize             = 2 * 1024 * 1024;
+pub const CONFIG_FLASH_SIZE: u32        = 64 * 1024;    // Reserved at the end of flash in memory.x
+pub const CONFIG_FLASH_OFFSET: u32      = FLASH_SIZE as u32 - CONFIG_FLASH_SIZE;
@@ -2,4 +2,9 @@
 #![no_main]
 
 pub mod definitions;
-pub mod custom_hid;
\ No newline at end of file
+pub mod custom_hid;
+pub mod protocol;
+pub mod config;
+pub mod config_store;
+pub mod storage;
+pub mod cli;
\ No newline at end of file
@@ -0,0 +1,621 @@
+use core::fmt;
+
+use heapless::{String, Vec};
+
+use crate::{
+    custom_hid::{HidInstruction, HidInstructionArr, KeyBitmap, KEY_BITMAP_SIZE, GamepadReport},
+    config::{ConfigValue, ConfigError, Hand, MAX_VALUE_SIZE, PROFILE_NAME_LEN},
+};
+
+// Every message on the glove <-> dongle link is framed as:
+// [tag: u8][payload length: u8][payload: big endian bytes]
+pub const FRAME_HEADER_SIZE: usize  = 2;
+pub const MAX_PAYLOAD_SIZE: usize   = 128;
+pub const MAX_FRAME_SIZE: usize     = FRAME_HEADER_SIZE + MAX_PAYLOAD_SIZE;
+pub const DECODER_SIZE: usize       = 4 * MAX_FRAME_SIZE;
+
+pub type Frame = Vec<u8, MAX_FRAME_SIZE>;
+
+const HID_PAYLOAD_SIZE: usize = size_of::<HidInstructionArr>();
+
+// Glove to dongle tags
+const TAG_HID: u8           = 0x01;
+const TAG_BATTERY: u8       = 0x02;
+const TAG_POWER: u8         = 0x03;
+const TAG_CONFIG_REPLY: u8  = 0x04;
+const TAG_HELLO: u8         = 0x05;
+const TAG_PONG: u8          = 0x06;
+const TAG_GAMEPAD: u8       = 0x07;
+const TAG_TELEMETRY: u8     = 0x08;
+// Dongle to glove tags
+const TAG_CONFIG_GET: u8    = 0x81;
+const TAG_CONFIG_SET: u8    = 0x82;
+const TAG_REBOOT: u8        = 0x83;
+const TAG_PROFILE: u8       = 0x84;
+const TAG_HAPTIC: u8        = 0x85;
+const TAG_PING: u8          = 0x86;
+const TAG_KEYBOARD_LEDS: u8 = 0x87;
+const TAG_CALIBRATE: u8     = 0x88;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    UnknownTag(u8),
+    BadLength { tag: u8, len: usize },
+    BadValue(u8),
+}
+
+/// Message that can travel over the link
+pub trait LinkMessage: Sized {
+    fn to_frame(&self) -> Frame;
+    fn from_frame(tag: u8, payload: &[u8]) -> Result<Self, ProtocolError>;
+}
+
+/// Builds a frame from a tag and its payload
+fn frame(tag: u8, payload: &[u8]) -> Frame {
+    let mut frame = Frame::new();
+    // Payloads are fixed by this module and always fit
+    frame.push(tag).unwrap();
+    frame.push(payload.len() as u8).unwrap();
+    frame.extend_from_slice(payload).unwrap();
+    frame
+}
+
+/// Checks the payload length before decoding it
+fn expect_len<const N: usize>(tag: u8, payload: &[u8]) -> Result<[u8; N], ProtocolError> {
+    payload.try_into().map_err(|_| ProtocolError::BadLength { tag, len: payload.len() })
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryStatus {
+    /// VSYS voltage
+    pub millivolts: u16,
+    /// Estimated state of charge, 0 to 100
+    pub percent: u8,
+}
+
+impl BatteryStatus {
+    pub fn from_be_bytes(data: [u8; 3]) -> Self {
+        BatteryStatus {
+            millivolts: u16::from_be_bytes([data[0], data[1]]),
+            percent:    data[2],
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 3] {
+        let millivolts = self.millivolts.to_be_bytes();
+        [millivolts[0], millivolts[1], self.percent]
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    /// Idle, waiting for motion at low power
+    Sleep,
+}
+
+impl PowerState {
+    pub fn from_be_bytes(data: [u8; 1]) -> Self {
+        match data[0] {
+            0 => PowerState::Active,
+            _ => PowerState::Sleep,
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 1] {
+        match self {
+            PowerState::Active => [0],
+            PowerState::Sleep => [1],
+        }
+    }
+}
+
+
+/// Why the glove refused a configuration request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigRejection {
+    UnknownKey,
+    WrongType,
+    OutOfRange,
+    /// Applied but couldn't be persisted
+    Storage,
+    UnknownProfile,
+    ProfilesFull,
+    ProfileInUse,
+}
+
+impl ConfigRejection {
+    fn code(&self) -> u8 {
+        match self {
+            ConfigRejection::UnknownKey     => 1,
+            ConfigRejection::WrongType      => 2,
+            ConfigRejection::OutOfRange     => 3,
+            ConfigRejection::Storage        => 4,
+            ConfigRejection::UnknownProfile => 5,
+            ConfigRejection::ProfilesFull   => 6,
+            ConfigRejection::ProfileInUse   => 7,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(ConfigRejection::UnknownKey),
+            2 => Some(ConfigRejection::WrongType),
+            3 => Some(ConfigRejection::OutOfRange),
+            4 => Some(ConfigRejection::Storage),
+            5 => Some(ConfigRejection::UnknownProfile),
+            6 => Some(ConfigRejection::ProfilesFull),
+            7 => Some(ConfigRejection::ProfileInUse),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigRejection::UnknownKey => write!(f, "unknown key"),
+            ConfigRejection::WrongType => write!(f, "wrong value type"),
+            ConfigRejection::OutOfRange => write!(f, "value out of range"),
+            ConfigRejection::Storage => write!(f, "applied but not saved"),
+            ConfigRejection::UnknownProfile => write!(f, "no profile with that name"),
+            ConfigRejection::ProfilesFull => write!(f, "no free profile slot"),
+            ConfigRejection::ProfileInUse => write!(f, "the profile is active"),
+        }
+    }
+}
+
+impl From<ConfigError> for ConfigRejection {
+    fn from(error: ConfigError) -> Self {
+        match error {
+            ConfigError::WrongType => ConfigRejection::WrongType,
+            ConfigError::OutOfRange => ConfigRejection::OutOfRange,
+            ConfigError::UnknownProfile => ConfigRejection::UnknownProfile,
+            ConfigError::ProfilesFull => ConfigRejection::ProfilesFull,
+            ConfigError::ProfileInUse => ConfigRejection::ProfileInUse,
+        }
+    }
+}
+
+/// Answer to a configuration request, with the value the glove now holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigReply {
+    /// Sequence number of the request
+    pub seq: u8,
+    /// Raw key id, the glove may not know it
+    pub key: u8,
+    pub result: Result<ConfigValue, ConfigRejection>,
+}
+
+impl ConfigReply {
+    /// Encodes as [seq][key][status, 0 if ok][value if ok]
+    fn to_payload(&self, buf: &mut [u8; 3 + MAX_VALUE_SIZE]) -> usize {
+        buf[0] = self.seq;
+        buf[1] = self.key;
+        match &self.result {
+            Ok(value) => {
+                buf[2] = 0;
+                let mut data = [0; MAX_VALUE_SIZE];
+                let len = value.to_bytes(&mut data);
+                buf[3..3 + len].copy_from_slice(&data[..len]);
+                3 + len
+            }
+            Err(rejection) => {
+                buf[2] = rejection.code();
+                3
+            }
+        }
+    }
+
+    fn from_payload(tag: u8, payload: &[u8]) -> Result<Self, ProtocolError> {
+        let [seq, key, status, value @ ..] = payload else {
+            return Err(ProtocolError::BadLength { tag, len: payload.len() });
+        };
+        let result = match status {
+            0 => Ok(ConfigValue::from_bytes(value).ok_or(ProtocolError::BadValue(tag))?),
+            code => Err(ConfigRejection::from_code(*code).ok_or(ProtocolError::BadValue(tag))?),
+        };
+        Ok(ConfigReply { seq: *seq, key: *key, result })
+    }
+}
+
+
+/// First message of a glove on every connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    /// Unique per glove, taken from its WiFi MAC address
+    pub device_id: u32,
+    pub hand: Hand,
+}
+
+impl Hello {
+    pub fn from_be_bytes(data: [u8; 5]) -> Option<Self> {
+        Some(Hello {
+            device_id:  u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            hand:       Hand::from_be_bytes([data[4]])?,
+        })
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 5] {
+        let id = self.device_id.to_be_bytes();
+        [id[0], id[1], id[2], id[3], self.hand.to_be_bytes()[0]]
+    }
+}
+
+
+pub const TELEMETRY_FINGERS: usize = 5;
+pub const TELEMETRY_SIZE: usize = 4 + 2 * (4 + 3 * 3 + TELEMETRY_FINGERS) + 1;
+
+/// Sensor state of a glove for host-side apps, sent at the `telemetry_rate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Telemetry {
+    /// Glove uptime when the sample was read
+    pub timestamp_ms: u32,
+    /// Orientation as w, x, y, z in Q14 fixed point (16384 = 1.0)
+    pub quaternion: [i16; 4],
+    /// Raw IMU readings, x, y, z
+    pub accel: [i16; 3],
+    pub gyro: [i16; 3],
+    pub mag: [i16; 3],
+    /// Raw flex readings from thumb to pinky, 0 for fingers without a sensor
+    pub flexes: [u16; TELEMETRY_FINGERS],
+    pub tap: bool,
+}
+
+impl Telemetry {
+    pub fn from_be_bytes(data: [u8; TELEMETRY_SIZE]) -> Self {
+        let mut words = data[4..TELEMETRY_SIZE - 1].chunks_exact(2).map(|word| u16::from_be_bytes([word[0], word[1]]));
+        let mut next = || words.next().unwrap_or(0);
+        Telemetry {
+            timestamp_ms:   u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            quaternion:     core::array::from_fn(|_| next() as i16),
+            accel:          core::array::from_fn(|_| next() as i16),
+            gyro:           core::array::from_fn(|_| next() as i16),
+            mag:            core::array::from_fn(|_| next() as i16),
+            flexes:         core::array::from_fn(|_| next()),
+            tap:            data[TELEMETRY_SIZE - 1] != 0,
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; TELEMETRY_SIZE] {
+        let mut data = [0; TELEMETRY_SIZE];
+        data[..4].copy_from_slice(&self.timestamp_ms.to_be_bytes());
+        let words = self.quaternion.iter()
+            .chain(&self.accel)
+            .chain(&self.gyro)
+            .chain(&self.mag)
+            .map(|word| *word as u16)
+            .chain(self.flexes);
+        for (bytes, word) in data[4..TELEMETRY_SIZE - 1].chunks_exact_mut(2).zip(words) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        data[TELEMETRY_SIZE - 1] = self.tap as u8;
+        data
+    }
+}
+
+
+/// Messages sent from the glove to the dongle
+#[derive(Debug)]
+pub enum GloveMessage {
+    Hid(HidInstruction),
+    Battery(BatteryStatus),
+    Power(PowerState),
+    ConfigReply(ConfigReply),
+    Hello(Hello),
+    /// Answer to a heartbeat, with the signal strength the glove receives in dBm
+    Pong { seq: u16, rssi: i8 },
+    /// Sent instead of `Hid` while the glove is in gamepad mode
+    Gamepad(GamepadReport),
+    /// Sensor stream, only while the `telemetry_rate` is set
+    Telemetry(Telemetry),
+}
+
+impl LinkMessage for GloveMessage {
+    fn to_frame(&self) -> Frame {
+        match self {
+            GloveMessage::Hid(instruction) => {
+                // Extra keys follow the instruction, older dongles only know the short form
+                let mut payload = [0; HID_PAYLOAD_SIZE + KEY_BITMAP_SIZE];
+                payload[..HID_PAYLOAD_SIZE].copy_from_slice(&instruction.to_be_bytes());
+                payload[HID_PAYLOAD_SIZE..].copy_from_slice(&instruction.extra_keys.0);
+                let len = match instruction.extra_keys.is_empty() {
+                    true => HID_PAYLOAD_SIZE,
+                    false => payload.len(),
+                };
+                frame(TAG_HID, &payload[..len])
+            }
+            GloveMessage::Battery(status) => frame(TAG_BATTERY, &status.to_be_bytes()),
+            GloveMessage::Power(state) => frame(TAG_POWER, &state.to_be_bytes()),
+            GloveMessage::ConfigReply(reply) => {
+                let mut payload = [0; 3 + MAX_VALUE_SIZE];
+                let len = reply.to_payload(&mut payload);
+                frame(TAG_CONFIG_REPLY, &payload[..len])
+            }
+            GloveMessage::Hello(hello) => frame(TAG_HELLO, &hello.to_be_bytes()),
+            GloveMessage::Pong { seq, rssi } => {
+                let seq = seq.to_be_bytes();
+                frame(TAG_PONG, &[seq[0], seq[1], *rssi as u8])
+            }
+            GloveMessage::Gamepad(report) => frame(TAG_GAMEPAD, &report.to_be_bytes()),
+            GloveMessage::Telemetry(telemetry) => frame(TAG_TELEMETRY, &telemetry.to_be_bytes()),
+        }
+    }
+
+    fn from_frame(tag: u8, payload: &[u8]) -> Result<Self, ProtocolError> {
+        match tag {
+            TAG_HID => {
+                if payload.len() < HID_PAYLOAD_SIZE {
+                    return Err(ProtocolError::BadLength { tag, len: payload.len() });
+                }
+                let (data, extra_keys) = payload.split_at(HID_PAYLOAD_SIZE);
+                let mut instruction = HidInstruction::from_be_bytes(expect_len(tag, data)?);
+                if !extra_keys.is_empty() {
+                    instruction.extra_keys = KeyBitmap(expect_len(tag, extra_keys)?);
+                }
+                Ok(GloveMessage::Hid(instruction))
+            }
+            TAG_BATTERY => Ok(GloveMessage::Battery(BatteryStatus::from_be_bytes(expect_len(tag, payload)?))),
+            TAG_POWER => Ok(GloveMessage::Power(PowerState::from_be_bytes(expect_len(tag, payload)?))),
+            TAG_CONFIG_REPLY => Ok(GloveMessage::ConfigReply(ConfigReply::from_payload(tag, payload)?)),
+            TAG_HELLO => {
+                let hello = Hello::from_be_bytes(expect_len(tag, payload)?).ok_or(ProtocolError::BadValue(tag))?;
+                Ok(GloveMessage::Hello(hello))
+            }
+            TAG_PONG => {
+                let [seq_high, seq_low, rssi] = expect_len::<3>(tag, payload)?;
+                Ok(GloveMessage::Pong { seq: u16::from_be_bytes([seq_high, seq_low]), rssi: rssi as i8 })
+            }
+            TAG_GAMEPAD => Ok(GloveMessage::Gamepad(GamepadReport::from_be_bytes(expect_len(tag, payload)?))),
+            TAG_TELEMETRY => Ok(GloveMessage::Telemetry(Telemetry::from_be_bytes(expect_len(tag, payload)?))),
+            _ => Err(ProtocolError::UnknownTag(tag)),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileAction {
+    /// Names of the stored profiles, the name is ignored
+    List,
+    Load,
+    /// Stores the active profile under the name
+    Save,
+    Delete,
+}
+
+impl ProfileAction {
+    fn code(&self) -> u8 {
+        match self {
+            ProfileAction::List     => 0,
+            ProfileAction::Load     => 1,
+            ProfileAction::Save     => 2,
+            ProfileAction::Delete   => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ProfileAction::List),
+            1 => Some(ProfileAction::Load),
+            2 => Some(ProfileAction::Save),
+            3 => Some(ProfileAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+
+/// Vibration played by the glove motor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HapticPattern {
+    /// Single short pulse, such as for a click
+    Tick,
+    /// Two short pulses, such as for a mode change
+    DoublePulse,
+    /// One long pulse, such as for a low battery
+    LongBuzz,
+}
+
+impl HapticPattern {
+    pub fn from_be_bytes(data: [u8; 1]) -> Option<Self> {
+        match data[0] {
+            0 => Some(HapticPattern::Tick),
+            1 => Some(HapticPattern::DoublePulse),
+            2 => Some(HapticPattern::LongBuzz),
+            _ => None,
+        }
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 1] {
+        match self {
+            HapticPattern::Tick         => [0],
+            HapticPattern::DoublePulse  => [1],
+            HapticPattern::LongBuzz     => [2],
+        }
+    }
+}
+
+
+/// Messages sent from the dongle to the glove
+#[derive(Debug)]
+pub enum DongleMessage {
+    /// Read a setting, key ids as in `ConfigKey::id`
+    ConfigGet { seq: u8, key: u8 },
+    /// Change and persist a setting
+    ConfigSet { seq: u8, key: u8, value: ConfigValue },
+    Reboot,
+    /// Answered with the active profile name as `ConfigKey::ActiveProfile`,
+    /// or the space separated stored names when listing
+    Profile { seq: u8, action: ProfileAction, name: String<PROFILE_NAME_LEN> },
+    /// Play a vibration, there's no reply
+    Haptic(HapticPattern),
+    /// Heartbeat, answered right away with a pong
+    Ping { seq: u16 },
+    /// Keyboard LEDs the host just set, bits as in `custom_hid::LED_*`
+    KeyboardLeds(u8),
+    /// Measure the flex bands, answered once done with the new bands as text
+    Calibrate { seq: u8 },
+}
+
+impl LinkMessage for DongleMessage {
+    fn to_frame(&self) -> Frame {
+        match self {
+            DongleMessage::ConfigGet { seq, key } => frame(TAG_CONFIG_GET, &[*seq, *key]),
+            DongleMessage::ConfigSet { seq, key, value } => {
+                let mut payload = [0; 2 + MAX_VALUE_SIZE];
+                let mut data = [0; MAX_VALUE_SIZE];
+                let len = value.to_bytes(&mut data);
+                payload[0] = *seq;
+                payload[1] = *key;
+                payload[2..2 + len].copy_from_slice(&data[..len]);
+                frame(TAG_CONFIG_SET, &payload[..2 + len])
+            }
+            DongleMessage::Reboot => frame(TAG_REBOOT, &[]),
+            DongleMessage::Profile { seq, action, name } => {
+                let mut payload = [0; 2 + PROFILE_NAME_LEN];
+                payload[0] = *seq;
+                payload[1] = action.code();
+                payload[2..2 + name.len()].copy_from_slice(name.as_bytes());
+                frame(TAG_PROFILE, &payload[..2 + name.len()])
+            }
+            DongleMessage::Haptic(pattern) => frame(TAG_HAPTIC, &pattern.to_be_bytes()),
+            DongleMessage::Ping { seq } => frame(TAG_PING, &seq.to_be_bytes()),
+            DongleMessage::KeyboardLeds(leds) => frame(TAG_KEYBOARD_LEDS, &[*leds]),
+            DongleMessage::Calibrate { seq } => frame(TAG_CALIBRATE, &[*seq]),
+        }
+    }
+
+    fn from_frame(tag: u8, payload: &[u8]) -> Result<Self, ProtocolError> {
+        match tag {
+            TAG_CONFIG_GET => {
+                let [seq, key] = expect_len::<2>(tag, payload)?;
+                Ok(DongleMessage::ConfigGet { seq, key })
+            }
+            TAG_CONFIG_SET => {
+                let [seq, key, value @ ..] = payload else {
+                    return Err(ProtocolError::BadLength { tag, len: payload.len() });
+                };
+                let value = ConfigValue::from_bytes(value).ok_or(ProtocolError::BadValue(tag))?;
+                Ok(DongleMessage::ConfigSet { seq: *seq, key: *key, value })
+            }
+            TAG_REBOOT => {
+                expect_len::<0>(tag, payload)?;
+                Ok(DongleMessage::Reboot)
+            }
+            TAG_PROFILE => {
+                let [seq, action, name @ ..] = payload else {
+                    return Err(ProtocolError::BadLength { tag, len: payload.len() });
+                };
+                let action = ProfileAction::from_code(*action).ok_or(ProtocolError::BadValue(tag))?;
+                let name = core::str::from_utf8(name).ok()
+                    .and_then(|name| String::try_from(name).ok())
+                    .ok_or(ProtocolError::BadValue(tag))?;
+                Ok(DongleMessage::Profile { seq: *seq, action, name })
+            }
+            TAG_HAPTIC => {
+                let pattern = HapticPattern::from_be_bytes(expect_len(tag, payload)?).ok_or(ProtocolError::BadValue(tag))?;
+                Ok(DongleMessage::Haptic(pattern))
+            }
+            TAG_PING => Ok(DongleMessage::Ping { seq: u16::from_be_bytes(expect_len(tag, payload)?) }),
+            TAG_KEYBOARD_LEDS => {
+                let [leds] = expect_len::<1>(tag, payload)?;
+                Ok(DongleMessage::KeyboardLeds(leds))
+            }
+            TAG_CALIBRATE => {
+                let [seq] = expect_len::<1>(tag, payload)?;
+                Ok(DongleMessage::Calibrate { seq })
+            }
+            _ => Err(ProtocolError::UnknownTag(tag)),
+        }
+    }
+}
+
+
+/// Reassembles frames out of a byte stream
+pub struct FrameDecoder {
+    buf: [u8; DECODER_SIZE],
+    len: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        FrameDecoder { buf: [0; DECODER_SIZE], len: 0 }
+    }
+
+    /// Drops any partially received frame
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Free space to read stream bytes into, to be confirmed with `filled`
+    pub fn unfilled(&mut self) -> &mut [u8] {
+        &mut self.buf[self.len..]
+    }
+
+    /// Confirms `count` bytes were written into `unfilled`
+    pub fn filled(&mut self, count: usize) {
+        self.len = (self.len + count).min(DECODER_SIZE);
+    }
+
+    /// Pops the next complete frame, if any, as a message
+    pub fn next_message<M: LinkMessage>(&mut self) -> Option<Result<M, ProtocolError>> {
+        if self.len < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let (tag, payload_len) = (self.buf[0], self.buf[1] as usize);
+        if payload_len > MAX_PAYLOAD_SIZE {
+            // Stream is out of sync, nothing in the buffer can be trusted
+            self.clear();
+            return Some(Err(ProtocolError::BadLength { tag, len: payload_len }));
+        }
+        let frame_len = FRAME_HEADER_SIZE + payload_len;
+        if self.len < frame_len {
+            return None;
+        }
+
+        let message = M::from_frame(tag, &self.buf[FRAME_HEADER_SIZE..frame_len]);
+        self.buf.copy_within(frame_len..self.len, 0);
+        self.len -= frame_len;
+        Some(message)
+    }
+}
+
+
+// The dongle broadcasts a beacon over UDP so gloves find it without a fixed address:
+// [magic: 4 bytes][TCP port: u16]
+const BEACON_MAGIC: [u8; 4]     = *b"MGLV";
+pub const BEACON_SIZE: usize    = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beacon {
+    /// Port the dongle accepts gloves on
+    pub tcp_port: u16,
+}
+
+impl Beacon {
+    pub fn to_be_bytes(&self) -> [u8; BEACON_SIZE] {
+        let port = self.tcp_port.to_be_bytes();
+        let mut data = [0; BEACON_SIZE];
+        data[..4].copy_from_slice(&BEACON_MAGIC);
+        data[4..].copy_from_slice(&port);
+        data
+    }
+
+    /// None for datagrams that aren't a beacon
+    pub fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        let data: [u8; BEACON_SIZE] = data.try_into().ok()?;
+        if data[..4] != BEACON_MAGIC {
+            return None;
+        }
+        Some(Beacon { tcp_port: u16::from_be_bytes([data[4], data[5]]) })
+    }
+}
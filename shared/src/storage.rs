@@ -0,0 +1,23 @@
+use embassy_rp::{
+    flash::{Blocking, Flash},
+    peripherals::FLASH,
+};
+use embassy_sync::{
+    mutex::Mutex,
+    blocking_mutex::raw::CriticalSectionRawMutex,
+};
+
+use crate::{
+    config_store::ConfigStore,
+    definitions::{FLASH_SIZE, CONFIG_FLASH_OFFSET, CONFIG_FLASH_SIZE},
+};
+
+pub type FlashStore = ConfigStore<Flash<'static, FLASH, Blocking, FLASH_SIZE>>;
+/// Settings store shared by the tasks that persist settings. Every settings change
+/// holds it from reading the settings until saving and applying the change.
+pub type SharedStore = Mutex<CriticalSectionRawMutex, FlashStore>;
+
+/// Mounts the settings store on the flash region reserved in memory.x
+pub fn mount_store(flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>) -> FlashStore {
+    ConfigStore::new(flash, CONFIG_FLASH_OFFSET, CONFIG_FLASH_SIZE)
+}